@@ -0,0 +1,57 @@
+-- :checkhealth nvim-sdl. nvim-sdl installs this file and adds it to 'runtimepath' on startup.
+local M = {}
+
+local health = vim.health or require('health')
+local start = health.start or health.report_start
+local ok = health.ok or health.report_ok
+local info = health.info or health.report_info
+local warn = health.warn or health.report_warn
+local error = health.error or health.report_error
+
+function M.check()
+  start('nvim-sdl')
+  local chan = vim.g.nvim_sdl_channel
+  if not chan then
+    error('This nvim is not attached to nvim-sdl')
+    return
+  end
+  local success, client = pcall(vim.rpcrequest, chan, 'nvim_sdl_health')
+  if not success then
+    error('nvim-sdl did not answer: ' .. tostring(client))
+    return
+  end
+
+  if client.font_path ~= '' then
+    ok(string.format('Font: %s %dpt', client.font_path, client.font_size))
+  else
+    error('No font found')
+  end
+  info(string.format('Cell size: %dx%d px', client.cell_width, client.cell_height))
+  info(string.format('Renderer: %s, video driver: %s', client.renderer, client.video_driver))
+  if client.dpi > 0 then
+    info(string.format('DPI scale: %g, display: %g dpi', client.scale, client.dpi))
+  else
+    info(string.format('DPI scale: %g, display dpi unknown', client.scale))
+  end
+  info('UI extensions: ' .. table.concat(client.ui_options, ', '))
+  if client.config_path == '' then
+    warn('No config directory, neither $XDG_CONFIG_HOME nor $HOME is set')
+  elseif client.config_exists then
+    ok('Config file: ' .. client.config_path)
+  else
+    info('No config file, using defaults. It would be read from ' .. client.config_path)
+  end
+
+  start('nvim-sdl clipboard')
+  local provider = vim.fn['provider#clipboard#Executable']()
+  if provider ~= '' then
+    ok('Clipboard provider: ' .. provider)
+  else
+    warn('No clipboard provider, the "+ register and the copy action do not work', {
+      'The paste action reads the clipboard itself and still works.',
+      'See :help clipboard for the tools nvim can use.',
+    })
+  end
+end
+
+return M
@@ -0,0 +1,179 @@
+use std::collections::HashSet;
+
+use neovim_lib::Value;
+
+/// What the running nvim supports, from `nvim_get_api_info`.
+#[derive(Debug, Default)]
+pub struct ApiInfo {
+    /// The channel nvim talks to nvim-sdl on.
+    pub channel_id: i64,
+    /// `(major, minor, patch)`.
+    pub version: (i64, i64, i64),
+    functions: HashSet<String>,
+    ui_options: HashSet<String>,
+}
+
+fn map_get<'a>(map: &'a Value, key: &str) -> Option<&'a Value> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| k.as_str() == Some(key))
+        .map(|(_, v)| v)
+}
+
+impl ApiInfo {
+    /// Parses the `[channel_id, metadata]` pair `nvim_get_api_info` returns.
+    pub fn parse(info: &Value) -> Option<Self> {
+        let info = info.as_array()?;
+        let metadata = info.get(1)?;
+        let version = map_get(metadata, "version");
+        let version_part = |name| {
+            version
+                .and_then(|v| map_get(v, name))
+                .and_then(|v| v.as_i64())
+                .unwrap_or(0)
+        };
+        let functions = map_get(metadata, "functions")
+            .and_then(|f| f.as_array())
+            .map(|functions| {
+                functions
+                    .iter()
+                    .filter_map(|f| map_get(f, "name")?.as_str())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let ui_options = map_get(metadata, "ui_options")
+            .and_then(|o| o.as_array())
+            .map(|options| {
+                options
+                    .iter()
+                    .filter_map(|o| o.as_str())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self {
+            channel_id: info.first()?.as_i64()?,
+            version: (
+                version_part("major"),
+                version_part("minor"),
+                version_part("patch"),
+            ),
+            functions,
+            ui_options,
+        })
+    }
+
+    pub fn has_function(&self, name: &str) -> bool {
+        self.functions.contains(name)
+    }
+
+    /// Whether `nvim_ui_attach` accepts an option, e.g. "ext_multigrid".
+    pub fn has_ui_option(&self, name: &str) -> bool {
+        self.ui_options.contains(name)
+    }
+}
+
+/// Spells a mouse event as keys for `nvim_input`, for nvim versions without
+/// `nvim_input_mouse`. Those only know the global grid, and have no hover events.
+pub fn mouse_input_keys(
+    button: &str,
+    action: &str,
+    modifier: &str,
+    row: i64,
+    col: i64,
+) -> Option<String> {
+    let button_name = match button {
+        "left" => "Left",
+        "right" => "Right",
+        "middle" => "Middle",
+        "x1" => "X1",
+        "x2" => "X2",
+        "wheel" => "ScrollWheel",
+        _ => return None,
+    };
+    let action_name = match (button, action) {
+        ("wheel", "up") => "Up",
+        ("wheel", "down") => "Down",
+        ("wheel", "left") => "Left",
+        ("wheel", "right") => "Right",
+        ("wheel", _) => return None,
+        (_, "press") => "Mouse",
+        (_, "drag") => "Drag",
+        (_, "release") => "Release",
+        _ => return None,
+    };
+    Some(format!(
+        "<{}{}{}><{},{}>",
+        modifier, button_name, action_name, col, row
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(key: &str, value: Value) -> (Value, Value) {
+        (key.into(), value)
+    }
+
+    fn function(name: &str) -> Value {
+        Value::Map(vec![pair("name", name.into()), pair("since", 1.into())])
+    }
+
+    #[test]
+    fn parse_api_info() {
+        let metadata = Value::Map(vec![
+            pair(
+                "version",
+                Value::Map(vec![
+                    pair("major", 0.into()),
+                    pair("minor", 9.into()),
+                    pair("patch", 5.into()),
+                    pair("api_level", 11.into()),
+                ]),
+            ),
+            pair(
+                "functions",
+                Value::Array(vec![function("nvim_input"), function("nvim_paste")]),
+            ),
+            pair(
+                "ui_options",
+                Value::Array(vec!["rgb".into(), "ext_linegrid".into()]),
+            ),
+        ]);
+        let info = ApiInfo::parse(&Value::Array(vec![3.into(), metadata])).unwrap();
+        assert_eq!(info.channel_id, 3);
+        assert_eq!(info.version, (0, 9, 5));
+        assert!(info.has_function("nvim_paste"));
+        assert!(!info.has_function("nvim_input_mouse"));
+        assert!(info.has_ui_option("ext_linegrid"));
+        assert!(!info.has_ui_option("ext_multigrid"));
+    }
+
+    #[test]
+    fn parse_invalid_api_info() {
+        assert!(ApiInfo::parse(&Value::Nil).is_none());
+        assert!(ApiInfo::parse(&Value::Array(vec![1.into()])).is_none());
+        let info = ApiInfo::parse(&Value::Array(vec![1.into(), Value::Map(vec![])])).unwrap();
+        assert_eq!(info.version, (0, 0, 0));
+        assert!(!info.has_function("nvim_input"));
+    }
+
+    #[test]
+    fn mouse_keys() {
+        assert_eq!(
+            mouse_input_keys("left", "press", "", 2, 5),
+            Some("<LeftMouse><5,2>".into())
+        );
+        assert_eq!(
+            mouse_input_keys("right", "drag", "C-", 0, 1),
+            Some("<C-RightDrag><1,0>".into())
+        );
+        assert_eq!(
+            mouse_input_keys("wheel", "down", "S-", 3, 4),
+            Some("<S-ScrollWheelDown><4,3>".into())
+        );
+        assert_eq!(mouse_input_keys("move", "", "", 1, 1), None);
+    }
+}
@@ -0,0 +1,100 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Whether a grapheme cluster is in a right-to-left script, like Hebrew or Arabic, going by its
+/// first character.
+pub fn is_rtl(cluster: &str) -> bool {
+    matches!(
+        cluster.chars().next(),
+        // Hebrew, Arabic, Syriac, Thaana, NKo and the like, then the Hebrew and Arabic
+        // presentation forms.
+        Some(
+            '\u{0590}'..='\u{08ff}'
+            | '\u{fb1d}'..='\u{fdff}'
+            | '\u{fe70}'..='\u{fefc}'
+            | '\u{10800}'..='\u{10fff}'
+            | '\u{1e800}'..='\u{1efff}',
+        )
+    )
+}
+
+/// A grapheme cluster in a row of cells.
+#[derive(Debug, PartialEq)]
+pub struct Cluster {
+    pub col: usize,
+    /// The number of cells it covers.
+    pub width: usize,
+    pub text: String,
+}
+
+/// Groups the texts of a row of cells into grapheme clusters. The empty cells nvim sends after
+/// double-width characters widen the cluster before them, and clusters nvim split over
+/// several cells are joined again, as versions before 0.10 do with emoji ZWJ sequences.
+pub fn clusters(cells: &[&str]) -> Vec<Cluster> {
+    let mut result: Vec<Cluster> = Vec::new();
+    for (col, &text) in cells.iter().enumerate() {
+        if let Some(last) = result.last_mut() {
+            // Nothing ASCII continues a cluster, which saves segmenting most cells.
+            let joined = !last.text.is_empty()
+                && (text.is_empty()
+                    || (!text.is_ascii()
+                        && (last.text.clone() + text).graphemes(true).count() == 1));
+            if joined {
+                last.text.push_str(text);
+                last.width += 1;
+                continue;
+            }
+        }
+        result.push(Cluster {
+            col,
+            width: 1,
+            text: text.to_string(),
+        });
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster(col: usize, width: usize, text: &str) -> Cluster {
+        Cluster {
+            col,
+            width,
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn wide_characters_take_the_empty_cell() {
+        assert_eq!(
+            clusters(&["a", "漢", "", "b"]),
+            vec![cluster(0, 1, "a"), cluster(1, 2, "漢"), cluster(3, 1, "b")]
+        );
+    }
+
+    #[test]
+    fn split_clusters_are_joined() {
+        // A family emoji as nvim 0.9 sends it.
+        assert_eq!(
+            clusters(&["👨\u{200d}", "", "👩", ""]),
+            vec![cluster(0, 4, "👨\u{200d}👩")]
+        );
+        assert_eq!(clusters(&["e\u{301}", "x"]).len(), 2);
+        assert_eq!(
+            clusters(&["", "a"]),
+            vec![cluster(0, 1, ""), cluster(1, 1, "a")]
+        );
+    }
+
+    #[test]
+    fn rtl_scripts() {
+        assert!(is_rtl("ש"));
+        assert!(is_rtl("ب"));
+        // An Arabic presentation form, as nvim sends with 'arabicshape'.
+        assert!(is_rtl("\u{fe91}"));
+        assert!(!is_rtl("a"));
+        assert!(!is_rtl("漢"));
+        assert!(!is_rtl(""));
+    }
+}
@@ -0,0 +1,237 @@
+use std::cell::RefCell;
+use std::mem;
+use std::sync::mpsc::{SendError, SyncSender, TrySendError};
+
+use crate::neovim_connector::ClientEvent;
+
+/// How many events can wait for the connector before the UI holds new ones back.
+pub const QUEUE_SIZE: usize = 64;
+
+/// Sends input to the connector over a bounded channel. While the channel is full, events wait
+/// here and are merged with `coalesce`, so a busy nvim holds back a few merged events instead
+/// of an ever growing queue. Call `flush` every frame to send what was held back.
+pub struct ClientSender {
+    tx: SyncSender<ClientEvent>,
+    pending: RefCell<Vec<ClientEvent>>,
+}
+
+impl ClientSender {
+    pub fn new(tx: SyncSender<ClientEvent>) -> ClientSender {
+        ClientSender {
+            tx,
+            pending: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Queues an event behind any held back ones. Fails once the connector is gone.
+    pub fn send(&self, event: ClientEvent) -> Result<(), SendError<ClientEvent>> {
+        self.pending.borrow_mut().push(event);
+        self.flush()
+    }
+
+    /// Sends held back events until the channel is full again.
+    pub fn flush(&self) -> Result<(), SendError<ClientEvent>> {
+        let mut pending = self.pending.borrow_mut();
+        let mut events = coalesce(mem::take(&mut *pending)).into_iter();
+        while let Some(event) = events.next() {
+            match self.tx.try_send(event) {
+                Ok(()) => {}
+                Err(TrySendError::Full(event)) => {
+                    pending.push(event);
+                    pending.extend(events);
+                    break;
+                }
+                Err(TrySendError::Disconnected(event)) => return Err(SendError(event)),
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of events held back.
+    pub fn pending(&self) -> usize {
+        self.pending.borrow().len()
+    }
+}
+
+/// Merges input that piled up while nvim was busy, so a backlog costs a few calls instead of
+/// one per event: text is joined into one `nvim_input` call, a run of drags or hover moves
+/// only keeps the latest position, and a run of wheel events becomes a count. Everything else
+/// keeps its order.
+pub fn coalesce<I: IntoIterator<Item = ClientEvent>>(events: I) -> Vec<ClientEvent> {
+    let mut batch: Vec<ClientEvent> = Vec::new();
+    for event in events {
+        match (batch.last_mut(), event) {
+            (Some(ClientEvent::Text(text)), ClientEvent::Text(more)) => text.push_str(&more),
+            (
+                Some(ClientEvent::Mouse {
+                    button,
+                    action,
+                    modifier,
+                    grid,
+                    row,
+                    col,
+                    count,
+                }),
+                ClientEvent::Mouse {
+                    button: next_button,
+                    action: next_action,
+                    modifier: next_modifier,
+                    grid: next_grid,
+                    row: next_row,
+                    col: next_col,
+                    count: next_count,
+                },
+            ) if *button == next_button
+                && *action == next_action
+                && *modifier == next_modifier
+                && *grid == next_grid
+                && is_mergeable(action) =>
+            {
+                if button == "wheel" {
+                    *count += next_count;
+                }
+                *row = next_row;
+                *col = next_col;
+            }
+            (
+                Some(ClientEvent::WindowResize { cols, rows }),
+                ClientEvent::WindowResize {
+                    cols: next_cols,
+                    rows: next_rows,
+                },
+            ) => {
+                *cols = next_cols;
+                *rows = next_rows;
+            }
+            (_, event) => batch.push(event),
+        }
+    }
+    batch
+}
+
+/// Whether consecutive mouse events with this action can be merged. Presses and releases can't,
+/// nvim counts presses to detect double clicks.
+fn is_mergeable(action: &str) -> bool {
+    matches!(action, "drag" | "" | "up" | "down" | "left" | "right")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::sync_channel;
+
+    fn mouse(button: &str, action: &str, row: i64, col: i64) -> ClientEvent {
+        ClientEvent::Mouse {
+            button: button.into(),
+            action: action.into(),
+            modifier: "".into(),
+            grid: 0,
+            row,
+            col,
+            count: 1,
+        }
+    }
+
+    #[test]
+    fn text_is_joined() {
+        let events = vec![
+            ClientEvent::Text("a".into()),
+            ClientEvent::Text("<CR>".into()),
+            ClientEvent::Paste("x".into()),
+            ClientEvent::Text("b".into()),
+        ];
+        assert_eq!(
+            coalesce(events),
+            vec![
+                ClientEvent::Text("a<CR>".into()),
+                ClientEvent::Paste("x".into()),
+                ClientEvent::Text("b".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn drags_keep_the_latest_position() {
+        let events = vec![
+            mouse("left", "press", 1, 1),
+            mouse("left", "drag", 1, 2),
+            mouse("left", "drag", 2, 3),
+            mouse("left", "drag", 3, 4),
+            mouse("left", "release", 3, 4),
+            mouse("move", "", 4, 4),
+            mouse("move", "", 5, 5),
+        ];
+        assert_eq!(
+            coalesce(events),
+            vec![
+                mouse("left", "press", 1, 1),
+                mouse("left", "drag", 3, 4),
+                mouse("left", "release", 3, 4),
+                mouse("move", "", 5, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn clicks_are_not_merged() {
+        let events = vec![mouse("left", "press", 1, 1), mouse("left", "press", 1, 1)];
+        assert_eq!(coalesce(events).len(), 2);
+    }
+
+    #[test]
+    fn wheel_events_are_counted() {
+        let events = vec![
+            mouse("wheel", "down", 1, 1),
+            mouse("wheel", "down", 1, 2),
+            mouse("wheel", "down", 1, 2),
+            mouse("wheel", "up", 1, 2),
+        ];
+        let mut down = mouse("wheel", "down", 1, 2);
+        if let ClientEvent::Mouse { count, .. } = &mut down {
+            *count = 3;
+        }
+        assert_eq!(coalesce(events), vec![down, mouse("wheel", "up", 1, 2)]);
+    }
+
+    #[test]
+    fn only_the_last_resize_is_kept() {
+        let events = vec![
+            ClientEvent::WindowResize { cols: 80, rows: 24 },
+            ClientEvent::WindowResize { cols: 90, rows: 30 },
+        ];
+        assert_eq!(
+            coalesce(events),
+            vec![ClientEvent::WindowResize { cols: 90, rows: 30 }]
+        );
+    }
+
+    #[test]
+    fn holds_back_and_merges_when_full() {
+        let (tx, rx) = sync_channel(1);
+        let sender = ClientSender::new(tx);
+        sender.send(ClientEvent::Text("a".into())).unwrap();
+        sender.send(ClientEvent::Text("b".into())).unwrap();
+        sender.send(mouse("wheel", "down", 1, 1)).unwrap();
+        sender.send(mouse("wheel", "down", 2, 1)).unwrap();
+        sender.send(ClientEvent::Text("c".into())).unwrap();
+        // "a" fits, the rest waits merged.
+        assert_eq!(sender.pending(), 3);
+        assert!(matches!(rx.try_recv(), Ok(ClientEvent::Text(ref t)) if t == "a"));
+        sender.flush().unwrap();
+        assert!(matches!(rx.try_recv(), Ok(ClientEvent::Text(ref t)) if t == "b"));
+        sender.flush().unwrap();
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(ClientEvent::Mouse {
+                row: 2,
+                count: 2,
+                ..
+            })
+        ));
+        sender.flush().unwrap();
+        assert!(matches!(rx.try_recv(), Ok(ClientEvent::Text(ref t)) if t == "c"));
+        assert_eq!(sender.pending(), 0);
+        drop(rx);
+        assert!(sender.send(ClientEvent::Focus(true)).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::shaping::{parse_features, FontFeature};
+use crate::shortcuts::{Action, Shortcuts};
+
+/// Settings read from `$XDG_CONFIG_HOME/nvim-sdl/config`.
+///
+/// The file is line based, with vim-like commands:
+///
+/// ```text
+/// # Comments start with '#'.
+/// set font_size=14
+/// map <D-=> zoom_in
+/// unmap <D-n>
+/// ```
+pub struct Config {
+    pub font_size: u16,
+    /// Fonts for characters the main font and `'guifontwide'` don't have, in `'guifont'` syntax,
+    /// e.g. `Noto Sans CJK JP,Symbols Nerd Font`. Installed fonts are searched after these.
+    pub font_fallback: String,
+    /// Whether bold text is drawn bold, with the bold face or emboldened by SDL_ttf.
+    pub bold: bool,
+    /// Whether italic text is drawn italic, with the italic face or slanted by SDL_ttf.
+    pub italic: bool,
+    /// OpenType features to turn on or off for ligatures and alternates, e.g. `ss01,-calt`.
+    /// Fonts keep their default features otherwise.
+    pub font_features: Vec<FontFeature>,
+    /// Opacity of the whole window, from 0 for invisible to 1 for opaque. Needs a compositing
+    /// window manager.
+    pub opacity: f32,
+    /// Wheel events sent to nvim per notch of the mouse wheel. Each one scrolls by
+    /// `'mousescroll'`, so with `mousescroll=ver:1,hor:1` this is the number of lines.
+    pub lines_per_notch: f32,
+    /// Whether two-finger scrolling on touchscreens keeps going after the fingers lift.
+    pub kinetic_scroll: bool,
+    /// Ex command run when the window is closed. nvim-sdl exits once nvim does.
+    pub quit_command: String,
+    pub shortcuts: Shortcuts,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            font_size: 16,
+            font_fallback: String::new(),
+            bold: true,
+            italic: true,
+            font_features: Vec::new(),
+            opacity: 1.0,
+            lines_per_notch: 1.0,
+            kinetic_scroll: true,
+            quit_command: "confirm qa".into(),
+            shortcuts: Shortcuts::default(),
+        }
+    }
+}
+
+/// Returns the location of the config file, whether or not it exists.
+pub fn config_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("nvim-sdl").join("config"))
+}
+
+impl Config {
+    /// Loads the config file, falling back to the defaults if there is none.
+    pub fn load() -> Self {
+        let mut config = Config::default();
+        if let Some(path) = config_path() {
+            if let Ok(contents) = fs::read_to_string(&path) {
+                for (i, line) in contents.lines().enumerate() {
+                    if let Err(e) = config.apply(line) {
+                        println!("{}:{}: {}", path.display(), i + 1, e);
+                    }
+                }
+            }
+        }
+        config
+    }
+
+    /// Applies a single line of the config file.
+    pub fn apply(&mut self, line: &str) -> Result<(), String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next(), words.next()) {
+            // The value is the rest of the line, so it may contain spaces.
+            (Some("set"), Some(_), ..) => self.set(line["set".len()..].trim_start()),
+            (Some("map"), Some(key), Some(action), None) => {
+                let action =
+                    Action::from_name(action).ok_or(format!("Unknown action: {}", action))?;
+                self.shortcuts.map(key, action);
+                Ok(())
+            }
+            (Some("unmap"), Some(key), None, None) => {
+                self.shortcuts.unmap(key);
+                Ok(())
+            }
+            _ => Err(format!("Invalid line: {}", line)),
+        }
+    }
+
+    fn set(&mut self, setting: &str) -> Result<(), String> {
+        let mut parts = setting.splitn(2, '=');
+        let name = parts.next().unwrap_or("");
+        let value = parts.next().ok_or(format!("Missing value: {}", setting))?;
+        match name {
+            "font_size" => self.font_size = parse_value(name, value)?,
+            "font_fallback" => self.font_fallback = value.into(),
+            "bold" => self.bold = parse_value(name, value)?,
+            "italic" => self.italic = parse_value(name, value)?,
+            "font_features" => self.font_features = parse_features(value)?,
+            "opacity" => {
+                let opacity: f32 = parse_value(name, value)?;
+                if !(0.0..=1.0).contains(&opacity) {
+                    return Err(format!("Invalid value for {}: {}", name, value));
+                }
+                self.opacity = opacity;
+            }
+            "lines_per_notch" => {
+                let lines: f32 = parse_value(name, value)?;
+                if !lines.is_finite() || lines <= 0.0 {
+                    return Err(format!("Invalid value for {}: {}", name, value));
+                }
+                self.lines_per_notch = lines;
+            }
+            "kinetic_scroll" => self.kinetic_scroll = parse_value(name, value)?,
+            "quit_command" => self.quit_command = value.into(),
+            _ => return Err(format!("Unknown setting: {}", name)),
+        }
+        Ok(())
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", name, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_lines() {
+        let mut config = Config::default();
+        assert_eq!(config.apply("# comment"), Ok(()));
+        assert_eq!(config.apply("   "), Ok(()));
+        assert_eq!(config.apply("set font_size=12"), Ok(()));
+        assert_eq!(config.font_size, 12);
+        assert_eq!(config.apply("set font_fallback=Noto Sans CJK JP"), Ok(()));
+        assert_eq!(config.font_fallback, "Noto Sans CJK JP");
+        assert_eq!(config.apply("set italic=false"), Ok(()));
+        assert!(!config.italic);
+        assert!(config.bold);
+        assert_eq!(config.apply("set font_features=ss01,-calt"), Ok(()));
+        assert_eq!(config.font_features.len(), 2);
+        assert_eq!(config.apply("set opacity=0.9"), Ok(()));
+        assert_eq!(config.opacity, 0.9);
+        assert_eq!(config.apply("set lines_per_notch=0.5"), Ok(()));
+        assert_eq!(config.lines_per_notch, 0.5);
+        assert_eq!(config.apply("set kinetic_scroll=false"), Ok(()));
+        assert!(!config.kinetic_scroll);
+        assert_eq!(config.apply("set quit_command=wqa | echo 'bye'"), Ok(()));
+        assert_eq!(config.quit_command, "wqa | echo 'bye'");
+        assert_eq!(config.apply("map <F11> toggle_fullscreen"), Ok(()));
+        assert_eq!(
+            config.shortcuts.lookup("<F11>"),
+            Some(Action::ToggleFullscreen)
+        );
+        assert_eq!(config.apply("unmap <F11>"), Ok(()));
+        assert_eq!(config.shortcuts.lookup("<F11>"), None);
+    }
+
+    #[test]
+    fn apply_errors() {
+        let mut config = Config::default();
+        assert!(config.apply("set font_size=big").is_err());
+        assert!(config.apply("set font_size").is_err());
+        assert!(config.apply("set nonsense=1").is_err());
+        assert!(config.apply("set opacity=2").is_err());
+        assert!(config.apply("set lines_per_notch=0").is_err());
+        assert!(config.apply("set lines_per_notch=-1").is_err());
+        assert!(config.apply("set lines_per_notch=NaN").is_err());
+        assert!(config.apply("set lines_per_notch=inf").is_err());
+        assert!(config.apply("set font_features=ligatures").is_err());
+        assert!(config.apply("map <F11> explode").is_err());
+        assert!(config.apply("map <F11>").is_err());
+        assert!(config.apply("bogus").is_err());
+        assert_eq!(config.font_size, 16);
+    }
+}
@@ -0,0 +1,165 @@
+use std::f32::consts::PI;
+
+use sdl2::rect::Rect;
+
+/// Lines drawn under or through text, from highlight attributes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decoration {
+    Underline,
+    Underdouble,
+    Undercurl,
+    Underdotted,
+    Underdashed,
+    Strikethrough,
+}
+
+/// Where decorations go in a cell, in pixels from the top of the cell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineMetrics {
+    pub underline: i32,
+    pub strikethrough: i32,
+    pub thickness: u32,
+}
+
+impl LineMetrics {
+    /// Scales the font's metrics, in font units above the baseline, to a cell `height` pixels
+    /// high with the baseline `ascent` pixels from the top. The underline is kept far enough
+    /// inside the cell for undercurls and double underlines.
+    pub fn new(
+        units_per_em: u32,
+        size: u16,
+        ascent: i32,
+        height: u32,
+        underline_position: f32,
+        underline_thickness: f32,
+        x_height: f32,
+    ) -> Self {
+        let scale = f32::from(size) / units_per_em.max(1) as f32;
+        let thickness = (underline_thickness * scale).round().max(1.0) as u32;
+        let half = thickness as i32 / 2;
+        let underline = ascent - (underline_position * scale).round() as i32 - half;
+        Self {
+            underline: underline.min(height as i32 - 2 * thickness as i32).max(0),
+            strikethrough: ascent - (x_height * scale / 2.0).round() as i32 - half,
+            thickness,
+        }
+    }
+}
+
+/// Cuts `x..x + width` into dashes `on` pixels long with `off` pixel gaps. Dashes line up with
+/// the window rather than the cell, so patterns continue across cells.
+fn dashes(x: i32, width: u32, on: u32, off: u32) -> Vec<(i32, u32)> {
+    let period = (on + off) as i32;
+    let end = x + width as i32;
+    let mut start = x - x.rem_euclid(period);
+    let mut result = Vec::new();
+    while start < end {
+        let from = start.max(x);
+        let to = (start + on as i32).min(end);
+        if from < to {
+            result.push((from, (to - from) as u32));
+        }
+        start += period;
+    }
+    result
+}
+
+/// The rectangles that draw `decoration` in a cell at `(x, y)`, `width` pixels wide.
+pub fn rects(
+    decoration: Decoration,
+    metrics: &LineMetrics,
+    x: i32,
+    y: i32,
+    width: u32,
+) -> Vec<Rect> {
+    let t = metrics.thickness;
+    let line = |top: i32| Rect::new(x, y + top, width, t);
+    let pattern = |on: u32, off: u32| {
+        dashes(x, width, on, off)
+            .into_iter()
+            .map(|(x, w)| Rect::new(x, y + metrics.underline, w, t))
+            .collect()
+    };
+    match decoration {
+        Decoration::Underline => vec![line(metrics.underline)],
+        Decoration::Underdouble => vec![
+            line(metrics.underline - t as i32),
+            line(metrics.underline + t as i32),
+        ],
+        Decoration::Underdotted => pattern(t, t),
+        Decoration::Underdashed => pattern(3 * t, 2 * t),
+        Decoration::Undercurl => {
+            // A sine wave, one pixel column at a time, in phase with the window's x.
+            let period = 6.0 * t as f32;
+            let amplitude = t as f32;
+            (x..x + width as i32)
+                .map(|px| {
+                    let phase = 2.0 * PI * px as f32 / period;
+                    let offset = (amplitude * phase.sin()).round() as i32;
+                    Rect::new(px, y + metrics.underline + offset, 1, t)
+                })
+                .collect()
+        }
+        Decoration::Strikethrough => vec![line(metrics.strikethrough)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METRICS: LineMetrics = LineMetrics {
+        underline: 14,
+        strikethrough: 8,
+        thickness: 1,
+    };
+
+    #[test]
+    fn scale_metrics() {
+        // 1000 units per em at 20px, so 50 units is a pixel.
+        let metrics = LineMetrics::new(1000, 20, 15, 20, -100.0, 50.0, 500.0);
+        assert_eq!(
+            metrics,
+            LineMetrics {
+                underline: 17,
+                strikethrough: 10,
+                thickness: 1,
+            }
+        );
+        // Underlines below the cell are moved up.
+        let metrics = LineMetrics::new(1000, 20, 15, 20, -400.0, 100.0, 500.0);
+        assert_eq!(metrics.underline, 16);
+        assert_eq!(metrics.thickness, 2);
+    }
+
+    #[test]
+    fn lines() {
+        assert_eq!(
+            rects(Decoration::Underline, &METRICS, 10, 20, 8),
+            vec![Rect::new(10, 34, 8, 1)]
+        );
+        assert_eq!(
+            rects(Decoration::Strikethrough, &METRICS, 10, 20, 8),
+            vec![Rect::new(10, 28, 8, 1)]
+        );
+        assert_eq!(rects(Decoration::Underdouble, &METRICS, 10, 20, 8).len(), 2);
+    }
+
+    #[test]
+    fn dashes_continue_across_cells() {
+        assert_eq!(dashes(0, 8, 3, 2), vec![(0, 3), (5, 3)]);
+        // The next cell starts in the gap after the dash at 5..8.
+        assert_eq!(dashes(8, 8, 3, 2), vec![(10, 3), (15, 1)]);
+        assert_eq!(dashes(3, 3, 1, 1), vec![(4, 1)]);
+    }
+
+    #[test]
+    fn undercurl_stays_near_underline() {
+        let curl = rects(Decoration::Undercurl, &METRICS, 7, 0, 8);
+        assert_eq!(curl.len(), 8);
+        assert!(curl
+            .iter()
+            .all(|r| (r.y() - 14).abs() <= 1 && r.width() == 1));
+        assert!(curl.iter().any(|r| r.y() != 14));
+    }
+}
@@ -0,0 +1,540 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use font_kit::family_name::FamilyName;
+use font_kit::handle::Handle;
+use font_kit::properties::{Properties, Style, Weight};
+use font_kit::source::SystemSource;
+use sdl2::pixels::Color;
+use sdl2::surface::Surface;
+use sdl2::ttf::{Font, FontStyle as TtfStyle, Sdl2TtfContext};
+
+use crate::decoration::LineMetrics;
+use crate::shaping::{Bitmap, FontFeature, ShapedRun, Shaper};
+use crate::system_fonts::SystemFonts;
+
+/// A face in a font file. `index` picks the face in font collections.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontFace {
+    pub path: PathBuf,
+    pub index: u32,
+}
+
+/// Which face of the font a cell is drawn with, from its highlight attributes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FontStyle {
+    pub bold: bool,
+    pub italic: bool,
+}
+
+/// A parsed `'guifont'`, e.g. `Fira Code,DejaVu Sans Mono:h13:b`.
+#[derive(Debug, Default, PartialEq)]
+pub struct GuiFont {
+    /// Families to try in order. Empty means the system's monospace font.
+    pub families: Vec<String>,
+    /// Size in points.
+    pub size: Option<f32>,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+impl GuiFont {
+    /// Parses a `'guifont'` value, see `:help guifont`. Options nvim-sdl doesn't use, like the
+    /// charset, are ignored.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut font = GuiFont::default();
+        let mut parts = value.split(':');
+        let mut family = String::new();
+        let mut chars = parts.next().unwrap_or("").chars();
+        while let Some(c) = chars.next() {
+            match c {
+                // "\," is a comma in a font name.
+                '\\' => family.extend(chars.next()),
+                ',' => font.families.push(family.split_off(0)),
+                // Spaces may be written as underscores.
+                '_' => family.push(' '),
+                _ => family.push(c),
+            }
+        }
+        font.families.push(family);
+        font.families = font
+            .families
+            .into_iter()
+            .map(|f| f.trim().to_string())
+            .filter(|f| !f.is_empty() && f != "*")
+            .collect();
+
+        for option in parts {
+            let mut chars = option.chars();
+            match (chars.next(), chars.as_str()) {
+                (Some('h'), size) => {
+                    let size = size
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|&size| size > 0.0)
+                        .ok_or(format!("Invalid font size: {}", option))?;
+                    font.size = Some(size);
+                }
+                (Some('b'), "") => font.bold = true,
+                (Some('i'), "") => font.italic = true,
+                _ => {}
+            }
+        }
+        Ok(font)
+    }
+
+    /// Finds the best matching installed face.
+    pub fn resolve(&self) -> Result<FontFace, String> {
+        let families: Vec<FamilyName> = if self.families.is_empty() {
+            vec![FamilyName::Monospace]
+        } else {
+            self.families.iter().map(|f| family_name(f)).collect()
+        };
+        let mut properties = Properties::new();
+        if self.bold {
+            properties.weight = Weight::BOLD;
+        }
+        if self.italic {
+            properties.style = Style::Italic;
+        }
+        match SystemSource::new().select_best_match(&families, &properties) {
+            Ok(Handle::Path { path, font_index }) => Ok(FontFace {
+                path,
+                index: font_index,
+            }),
+            Ok(Handle::Memory { .. }) => {
+                Err(format!("Font is not a file: {}", self.families.join(",")))
+            }
+            Err(_) => Err(format!("Font not found: {}", self.families.join(","))),
+        }
+    }
+
+    /// Finds the bold, italic and bold italic faces of the same families. `:b` and `:i` in
+    /// `'guifont'` apply to these too.
+    pub fn resolve_styles(&self) -> Vec<(FontStyle, FontFace)> {
+        [(true, false), (false, true), (true, true)]
+            .iter()
+            .filter_map(|&(bold, italic)| {
+                let font = GuiFont {
+                    families: self.families.clone(),
+                    bold: self.bold || bold,
+                    italic: self.italic || italic,
+                    ..*self
+                };
+                Some((FontStyle { bold, italic }, font.resolve().ok()?))
+            })
+            .collect()
+    }
+
+    /// Resolves every family on its own, for fallback lists like `'guifontwide'`. Returns an
+    /// error naming the families that aren't installed.
+    pub fn resolve_each(&self) -> (Vec<FontFace>, Result<(), String>) {
+        let mut faces = Vec::new();
+        let mut missing = Vec::new();
+        for family in &self.families {
+            let font = GuiFont {
+                families: vec![family.clone()],
+                ..*self
+            };
+            match font.resolve() {
+                Ok(face) => faces.push(face),
+                Err(_) => missing.push(family.as_str()),
+            }
+        }
+        let result = if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Font not found: {}", missing.join(",")))
+        };
+        (faces, result)
+    }
+}
+
+fn family_name(name: &str) -> FamilyName {
+    match name.to_lowercase().as_str() {
+        "monospace" => FamilyName::Monospace,
+        "serif" => FamilyName::Serif,
+        "sans-serif" | "sans" => FamilyName::SansSerif,
+        _ => FamilyName::Title(name.into()),
+    }
+}
+
+struct LoadedFont<'a> {
+    face: FontFace,
+    /// The style this is the primary font for. Fallbacks have none.
+    style: Option<FontStyle>,
+    /// Whether the face itself is bold or italic, which can differ from `style` when a family
+    /// lacks a face, or for fallbacks.
+    own: FontStyle,
+    /// Whether this is from `'guifontwide'`, tried first for double-width characters.
+    wide: bool,
+    /// Whether this was found among the installed fonts rather than configured.
+    installed: bool,
+    font: Font<'a, 'static>,
+    /// The font file, shared by `glyphs` and the shaper.
+    data: Arc<Vec<u8>>,
+    /// Used to check coverage, as SDL_ttf only answers for the Basic Multilingual Plane.
+    glyphs: font_kit::font::Font,
+    /// Loaded the first time a run is shaped with this font. `None` inside if it failed.
+    shaper: Option<Option<Shaper>>,
+}
+
+fn load<'a>(
+    ttf: &'a Sdl2TtfContext,
+    face: &FontFace,
+    style: Option<FontStyle>,
+    size: u16,
+) -> Result<LoadedFont<'a>, String> {
+    let font = ttf.load_font_at_index(&face.path, face.index, size)?;
+    // Kept for shaping too, so the file is only read once more.
+    let data = fs::read(&face.path).map_err(|e| format!("{}: {}", face.path.display(), e))?;
+    let data = Arc::new(data);
+    let glyphs = font_kit::font::Font::from_bytes(data.clone(), face.index)
+        .map_err(|e| format!("{}: {:?}", face.path.display(), e))?;
+    let properties = glyphs.properties();
+    let own = FontStyle {
+        bold: properties.weight >= Weight::SEMIBOLD,
+        italic: properties.style != Style::Normal,
+    };
+    Ok(LoadedFont {
+        face: face.clone(),
+        style,
+        own,
+        data,
+        wide: false,
+        installed: false,
+        font,
+        glyphs,
+        shaper: None,
+    })
+}
+
+impl LoadedFont<'_> {
+    fn has(&self, c: char) -> bool {
+        self.glyphs.glyph_for_char(c).is_some()
+    }
+
+    /// Whether SDL_ttf has to embolden or slant the font to draw `style`.
+    fn is_synthetic(&self, style: FontStyle) -> bool {
+        (style.bold && !self.own.bold) || (style.italic && !self.own.italic)
+    }
+
+    fn shaper(&mut self) -> Option<&Shaper> {
+        let (face, data) = (&self.face, &self.data);
+        self.shaper
+            .get_or_insert_with(|| match Shaper::new(data.clone(), face.index) {
+                Ok(shaper) => Some(shaper),
+                Err(e) => {
+                    println!(
+                        "Failed to load font for shaping: {}: {}",
+                        face.path.display(),
+                        e
+                    );
+                    None
+                }
+            })
+            .as_ref()
+    }
+}
+
+/// Characters that join or modify others in a cluster, which fonts may not have glyphs for.
+fn is_joiner(c: char) -> bool {
+    matches!(c, '\u{200d}' | '\u{fe00}'..='\u{fe0f}' | '\u{e0100}'..='\u{e01ef}')
+}
+
+/// The fonts a grid is drawn with: the primary font and its bold and italic faces, then
+/// fallbacks for characters those don't have. Installed fonts are searched when none of the
+/// fallbacks has a character either, and the ones found are kept at the end of the chain, also
+/// when the chain is reloaded.
+pub struct Fonts<'a> {
+    ttf: &'a Sdl2TtfContext,
+    size: u16,
+    fonts: Vec<LoadedFont<'a>>,
+    /// Which font draws each character seen so far, by style and whether it is double width.
+    /// `None` if no font has it.
+    coverage: HashMap<(char, FontStyle, bool), Option<usize>>,
+    system: SystemFonts,
+    /// Characters no installed font has.
+    missing: HashSet<char>,
+}
+
+impl<'a> Fonts<'a> {
+    /// Loads `faces`, primary first, and the faces for other `styles`. Faces also in `wide` are
+    /// preferred for double-width characters. Fonts other than the primary one that fail to
+    /// load are skipped.
+    pub fn new(
+        ttf: &'a Sdl2TtfContext,
+        faces: &[FontFace],
+        wide: &[FontFace],
+        styles: &[(FontStyle, FontFace)],
+        size: u16,
+    ) -> Result<Self, String> {
+        let (primary, fallbacks) = faces.split_first().ok_or("No font")?;
+        let mut fonts = vec![load(ttf, primary, None, size)?];
+        let styled = styles
+            .iter()
+            // Families without a bold or italic face give back the regular one.
+            .filter(|(_, face)| face != primary)
+            .map(|(style, face)| (face, Some(*style)));
+        for (face, style) in styled.chain(fallbacks.iter().map(|face| (face, None))) {
+            match load(ttf, face, style, size) {
+                Ok(font) => fonts.push(LoadedFont {
+                    wide: style.is_none() && wide.contains(face),
+                    ..font
+                }),
+                Err(e) => println!("Failed to load font: {}", e),
+            }
+        }
+        Ok(Self {
+            ttf,
+            size,
+            fonts,
+            coverage: HashMap::new(),
+            system: SystemFonts::default(),
+            missing: HashSet::new(),
+        })
+    }
+
+    /// Starts reading the installed fonts in the background, so they can be searched by the time
+    /// a character needs them.
+    pub fn index_system_fonts(&self) {
+        self.system.start();
+    }
+
+    /// Adds the installed fonts `other` found to the end of the chain, and shares what it knows
+    /// about installed fonts, for a chain that replaces `other`.
+    pub fn keep_system_fonts(&mut self, other: &Fonts<'a>) {
+        self.system = other.system.clone();
+        self.missing = other.missing.clone();
+        for found in other.fonts.iter().filter(|f| f.installed) {
+            if self.fonts.iter().any(|f| f.face == found.face) {
+                continue;
+            }
+            match load(self.ttf, &found.face, None, self.size) {
+                Ok(font) => self.fonts.push(LoadedFont {
+                    installed: true,
+                    ..font
+                }),
+                Err(e) => println!("Failed to load fallback font: {}", e),
+            }
+        }
+    }
+
+    /// Reloads the same chain at another size, keeping the installed fonts found so far.
+    pub fn resize(&mut self, size: u16) -> Result<(), String> {
+        let faces: Vec<FontFace> = self
+            .fonts
+            .iter()
+            .filter(|f| f.style.is_none() && !f.installed)
+            .map(|f| f.face.clone())
+            .collect();
+        let wide: Vec<FontFace> = self
+            .fonts
+            .iter()
+            .filter(|f| f.wide)
+            .map(|f| f.face.clone())
+            .collect();
+        let styles: Vec<(FontStyle, FontFace)> = self
+            .fonts
+            .iter()
+            .filter_map(|f| Some((f.style?, f.face.clone())))
+            .collect();
+        let mut fonts = Fonts::new(self.ttf, &faces, &wide, &styles, size)?;
+        fonts.keep_system_fonts(self);
+        *self = fonts;
+        Ok(())
+    }
+
+    pub fn primary(&self) -> &Font<'a, 'static> {
+        &self.fonts[0].font
+    }
+
+    /// Where underlines and strikethroughs go, from the primary font.
+    pub fn line_metrics(&self) -> LineMetrics {
+        let primary = &self.fonts[0];
+        let metrics = primary.glyphs.metrics();
+        LineMetrics::new(
+            metrics.units_per_em,
+            self.size,
+            primary.font.ascent(),
+            primary.font.height() as u32,
+            metrics.underline_position,
+            metrics.underline_thickness,
+            metrics.x_height,
+        )
+    }
+
+    /// Renders `text` with the font at `index`. SDL_ttf emboldens or slants it for a `style`
+    /// the face doesn't have itself, as many monospace fonts only come in regular.
+    pub fn render(
+        &mut self,
+        index: usize,
+        style: FontStyle,
+        text: &str,
+        color: Color,
+    ) -> Surface<'static> {
+        let font = &mut self.fonts[index];
+        let mut flags = TtfStyle::NORMAL;
+        if style.bold && !font.own.bold {
+            flags |= TtfStyle::BOLD;
+        }
+        if style.italic && !font.own.italic {
+            flags |= TtfStyle::ITALIC;
+        }
+        font.font.set_style(flags);
+        let surface = font.font.render(text).blended(color).unwrap();
+        font.font.set_style(TtfStyle::NORMAL);
+        surface
+    }
+
+    /// The distance from the top of the font's line to its baseline, in pixels.
+    pub fn ascent(&self, index: usize) -> i32 {
+        self.fonts[index].font.ascent()
+    }
+
+    /// Shapes a run of cells drawn with the font at `index` and returns the glyphs of the cells
+    /// shaping changed, e.g. ligatures or Arabic contextual forms. Styles SDL_ttf fakes aren't
+    /// shaped, as the glyphs couldn't be drawn the same way.
+    pub fn shape(
+        &mut self,
+        index: usize,
+        style: FontStyle,
+        cells: &[&str],
+        features: &[FontFeature],
+        rtl: bool,
+    ) -> ShapedRun {
+        let size = self.size;
+        let font = &mut self.fonts[index];
+        if font.is_synthetic(style) {
+            return ShapedRun::default();
+        }
+        font.shaper().map_or_else(ShapedRun::default, |shaper| {
+            shaper.shape(cells, features, size, rtl)
+        })
+    }
+
+    /// Rasterizes a glyph from `shape`.
+    pub fn rasterize(&mut self, index: usize, id: u16) -> Option<Bitmap> {
+        let size = self.size;
+        self.fonts[index].shaper()?.rasterize(id, size)
+    }
+
+    /// Returns the index of the font to draw a grapheme cluster with in `style`. The font is
+    /// picked for the first character, and only passed over if another loaded font has the
+    /// whole cluster, e.g. an accent the first one lacks.
+    pub fn lookup(&mut self, cluster: &str, style: FontStyle, wide: bool) -> usize {
+        let mut chars = cluster.chars().filter(|&c| !is_joiner(c));
+        let first = match chars.next() {
+            Some(c) => c,
+            None => return 0,
+        };
+        let index = self.lookup_char(first, style, wide);
+        let rest: Vec<char> = chars.collect();
+        if rest.iter().all(|&c| self.fonts[index].has(c)) {
+            return index;
+        }
+        // Only fonts for this style or for any style, not the face of another style.
+        self.fonts
+            .iter()
+            .position(|font| {
+                (font.style == Some(style) || font.style.is_none())
+                    && font.has(first)
+                    && rest.iter().all(|&c| font.has(c))
+            })
+            .unwrap_or(index)
+    }
+
+    /// Returns the index of the font to draw `c` with in `style`. Characters the face for the
+    /// style doesn't have come from the regular chain, where double-width characters try the
+    /// `'guifontwide'` fonts first. Characters no font has are drawn with the primary font.
+    fn lookup_char(&mut self, c: char, style: FontStyle, wide: bool) -> usize {
+        if let Some(&index) = self.coverage.get(&(c, style, wide)) {
+            return index.unwrap_or(0);
+        }
+        let find = |matches: &dyn Fn(&LoadedFont) -> bool| {
+            self.fonts
+                .iter()
+                .position(|font| matches(font) && font.has(c))
+        };
+        let mut index = find(&|font| font.style == Some(style))
+            .or_else(|| find(&|font| wide && font.wide))
+            .or_else(|| find(&|font| font.style.is_none()));
+        if index.is_none() && !c.is_control() && !self.missing.contains(&c) {
+            match self.search_system(c) {
+                Some(found) => index = found,
+                // Looked up again once the installed fonts are read.
+                None => return 0,
+            }
+        }
+        self.coverage.insert((c, style, wide), index);
+        index.unwrap_or(0)
+    }
+
+    /// Finds an installed font that has `c`, adding it to the chain. Returns `None` while the
+    /// installed fonts are still being read.
+    fn search_system(&mut self, c: char) -> Option<Option<usize>> {
+        for face in self.system.find(c)? {
+            if let Some(index) = self.fonts.iter().position(|f| f.face == face) {
+                return Some(Some(index));
+            }
+            match load(self.ttf, &face, None, self.size) {
+                Ok(font) => {
+                    self.fonts.push(LoadedFont {
+                        installed: true,
+                        ..font
+                    });
+                    return Some(Some(self.fonts.len() - 1));
+                }
+                Err(e) => println!("Failed to load fallback font: {}", e),
+            }
+        }
+        self.missing.insert(c);
+        Some(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_families_and_options() {
+        assert_eq!(
+            GuiFont::parse("Fira Code,DejaVu_Sans_Mono:h13.5:b:i:cANSI"),
+            Ok(GuiFont {
+                families: vec!["Fira Code".into(), "DejaVu Sans Mono".into()],
+                size: Some(13.5),
+                bold: true,
+                italic: true,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_defaults() {
+        assert_eq!(GuiFont::parse(""), Ok(GuiFont::default()));
+        assert_eq!(
+            GuiFont::parse("*:h10"),
+            Ok(GuiFont {
+                size: Some(10.0),
+                ..GuiFont::default()
+            })
+        );
+    }
+
+    #[test]
+    fn parse_escaped_comma() {
+        assert_eq!(
+            GuiFont::parse("Odd\\,Name, Other ").unwrap().families,
+            vec!["Odd,Name".to_string(), "Other".to_string()]
+        );
+    }
+
+    #[test]
+    fn parse_invalid_size() {
+        assert!(GuiFont::parse("Mono:hbig").is_err());
+        assert!(GuiFont::parse("Mono:h0").is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// Font size change, in points, for a pinch across the whole touch surface.
+const PINCH_POINTS: f32 = 100.0;
+/// How long a pinch has to stop before the grid is resized to the new font size.
+const PINCH_SETTLE: Duration = Duration::from_millis(200);
+/// Smallest change in finger distance that makes a gesture a pinch rather than a drag.
+const PINCH_THRESHOLD: f32 = 0.002;
+/// Lines scrolled by one wheel event until `'mousescroll'` is known, nvim's default.
+const DEFAULT_LINES_PER_WHEEL_EVENT: f32 = 3.0;
+/// Time constant of the exponential decay of kinetic scrolling, in seconds.
+const INERTIA_TAU: f32 = 0.325;
+/// Kinetic scrolling stops below this speed, in wheel events per second.
+const INERTIA_MIN_VELOCITY: f32 = 1.0;
+/// Fingers have to move right before they lift for kinetic scrolling to start.
+const INERTIA_MAX_PAUSE: Duration = Duration::from_millis(50);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Mode {
+    Pinch,
+    Drag,
+}
+
+/// Turns SDL's touch events into zooming and scrolling.
+///
+/// A two-finger gesture is either a pinch, which changes the font size, or a drag, which
+/// scrolls. The first significant movement decides which one it is until the fingers lift.
+#[derive(Default)]
+pub struct Gestures {
+    fingers: HashSet<i64>,
+    mode: Option<Mode>,
+    last_y: Option<f32>,
+    /// Font size change not yet applied, in points.
+    zoom: f32,
+    pinched_at: Option<Instant>,
+    /// Scroll speed in wheel events per second, positive for up.
+    velocity: f32,
+    dragged_at: Option<Instant>,
+    /// When kinetic scrolling last produced a step, while it is running.
+    inertia_at: Option<Instant>,
+    /// Lines scrolled by one wheel event, from the vertical part of `'mousescroll'`.
+    lines_per_wheel_event: Option<f32>,
+}
+
+/// Returns the lines one vertical wheel event scrolls, from a `'mousescroll'` value like
+/// `ver:3,hor:6`.
+pub fn mousescroll_lines(value: &str) -> Option<f32> {
+    value
+        .split(',')
+        .find_map(|part| part.strip_prefix("ver:"))
+        .and_then(|lines| lines.parse::<u32>().ok())
+        .map(|lines| lines as f32)
+}
+
+impl Gestures {
+    pub fn finger_down(&mut self, finger_id: i64) {
+        self.fingers.insert(finger_id);
+        self.inertia_at = None;
+    }
+
+    pub fn finger_up(&mut self, finger_id: i64, now: Instant, kinetic: bool) {
+        self.fingers.remove(&finger_id);
+        if self.fingers.len() >= 2 {
+            return;
+        }
+        let recent = self
+            .dragged_at
+            .is_some_and(|t| now.duration_since(t) <= INERTIA_MAX_PAUSE);
+        if kinetic
+            && self.mode == Some(Mode::Drag)
+            && recent
+            && self.velocity.abs() >= INERTIA_MIN_VELOCITY
+        {
+            self.inertia_at = Some(now);
+        }
+        self.mode = None;
+        self.last_y = None;
+        self.dragged_at = None;
+    }
+
+    pub fn set_lines_per_wheel_event(&mut self, lines: f32) {
+        self.lines_per_wheel_event = Some(lines);
+    }
+
+    /// Number of fingers on the touch surface.
+    pub fn fingers(&self) -> usize {
+        self.fingers.len()
+    }
+
+    /// Handles a `MultiGesture` event. `d_dist` and `y` are relative to the size of the touch
+    /// surface, and `rows` is the number of rows it covers.
+    ///
+    /// Returns the font size change in whole points and the scroll distance in wheel events,
+    /// positive for up.
+    pub fn multi_gesture(
+        &mut self,
+        d_dist: f32,
+        y: f32,
+        num_fingers: u16,
+        rows: f32,
+        now: Instant,
+    ) -> (i32, f32) {
+        if num_fingers != 2 {
+            return (0, 0.0);
+        }
+        let dy = self.last_y.map_or(0.0, |last_y| y - last_y);
+        self.last_y = Some(y);
+        if self.mode.is_none() {
+            if d_dist.abs() >= PINCH_THRESHOLD {
+                self.mode = Some(Mode::Pinch);
+            } else if dy != 0.0 {
+                self.mode = Some(Mode::Drag);
+            }
+        }
+
+        match self.mode {
+            Some(Mode::Pinch) => {
+                self.zoom += d_dist * PINCH_POINTS;
+                self.pinched_at = Some(now);
+                let points = self.zoom.trunc();
+                self.zoom -= points;
+                (points as i32, 0.0)
+            }
+            Some(Mode::Drag) => {
+                // Moving the fingers down pulls the text down, like scrolling the wheel up. With
+                // `mousescroll=ver:0` the wheel doesn't scroll, so neither does dragging.
+                let lines = self
+                    .lines_per_wheel_event
+                    .unwrap_or(DEFAULT_LINES_PER_WHEEL_EVENT);
+                let scroll = if lines > 0.0 { dy * rows / lines } else { 0.0 };
+                if let Some(dragged_at) = self.dragged_at {
+                    let dt = now.duration_since(dragged_at).as_secs_f32();
+                    if dt > 0.0 {
+                        self.velocity = 0.8 * self.velocity + 0.2 * scroll / dt;
+                    }
+                } else {
+                    self.velocity = 0.0;
+                }
+                self.dragged_at = Some(now);
+                (0, scroll)
+            }
+            None => (0, 0.0),
+        }
+    }
+
+    /// Advances kinetic scrolling and returns the distance to scroll in wheel events, and
+    /// whether a pinch has just settled so the grid should be resized.
+    pub fn tick(&mut self, now: Instant) -> (f32, bool) {
+        let settled = match self.pinched_at {
+            Some(t) if now.duration_since(t) >= PINCH_SETTLE => {
+                self.pinched_at = None;
+                true
+            }
+            _ => false,
+        };
+
+        let mut scroll = 0.0;
+        if let Some(last) = self.inertia_at {
+            let dt = now.duration_since(last).as_secs_f32();
+            let decay = (-dt / INERTIA_TAU).exp();
+            // The distance covered while the speed decays, so long frames don't overshoot.
+            scroll = self.velocity * INERTIA_TAU * (1.0 - decay);
+            self.velocity *= decay;
+            if self.velocity.abs() < INERTIA_MIN_VELOCITY {
+                self.velocity = 0.0;
+                self.inertia_at = None;
+            } else {
+                self.inertia_at = Some(now);
+            }
+        }
+        (scroll, settled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(t: Instant, ms: u64) -> Instant {
+        t + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn pinch_changes_font_size_then_settles() {
+        let mut g = Gestures::default();
+        let t = Instant::now();
+        g.finger_down(1);
+        g.finger_down(2);
+        assert_eq!(g.multi_gesture(0.006, 0.5, 2, 40.0, t), (0, 0.0));
+        assert_eq!(g.multi_gesture(0.006, 0.5, 2, 40.0, ms(t, 10)), (1, 0.0));
+        assert_eq!(g.multi_gesture(-0.03, 0.6, 2, 40.0, ms(t, 20)), (-2, 0.0));
+        assert_eq!(g.tick(ms(t, 100)), (0.0, false));
+        assert_eq!(g.tick(ms(t, 220)), (0.0, true));
+        assert_eq!(g.tick(ms(t, 300)), (0.0, false));
+    }
+
+    #[test]
+    fn drag_scrolls() {
+        let mut g = Gestures::default();
+        let t = Instant::now();
+        g.finger_down(1);
+        g.finger_down(2);
+        assert_eq!(g.multi_gesture(0.0, 0.5, 2, 30.0, t), (0, 0.0));
+        let (zoom, scroll) = g.multi_gesture(0.0, 0.6, 2, 30.0, ms(t, 10));
+        assert_eq!(zoom, 0);
+        assert!((scroll - 1.0).abs() < 1e-4, "{}", scroll);
+        // Once dragging, a change in distance doesn't zoom.
+        let (zoom, scroll) = g.multi_gesture(0.01, 0.4, 2, 30.0, ms(t, 20));
+        assert_eq!(zoom, 0);
+        assert!((scroll + 2.0).abs() < 1e-4, "{}", scroll);
+        assert_eq!(g.multi_gesture(0.0, 0.5, 3, 30.0, ms(t, 30)), (0, 0.0));
+    }
+
+    #[test]
+    fn drag_follows_mousescroll() {
+        let mut g = Gestures::default();
+        g.set_lines_per_wheel_event(1.0);
+        let t = Instant::now();
+        g.finger_down(1);
+        g.finger_down(2);
+        g.multi_gesture(0.0, 0.5, 2, 30.0, t);
+        let (_, scroll) = g.multi_gesture(0.0, 0.6, 2, 30.0, ms(t, 10));
+        assert!((scroll - 3.0).abs() < 1e-4, "{}", scroll);
+
+        g.set_lines_per_wheel_event(0.0);
+        assert_eq!(g.multi_gesture(0.0, 0.7, 2, 30.0, ms(t, 20)), (0, 0.0));
+    }
+
+    #[test]
+    fn parse_mousescroll() {
+        assert_eq!(mousescroll_lines("ver:3,hor:6"), Some(3.0));
+        assert_eq!(mousescroll_lines("hor:2,ver:1"), Some(1.0));
+        assert_eq!(mousescroll_lines("ver:0"), Some(0.0));
+        assert_eq!(mousescroll_lines("hor:6"), None);
+        assert_eq!(mousescroll_lines("ver:-1"), None);
+    }
+
+    #[test]
+    fn kinetic_scroll_decays() {
+        let mut g = Gestures::default();
+        let t = Instant::now();
+        g.finger_down(1);
+        g.finger_down(2);
+        for i in 0..10 {
+            g.multi_gesture(0.0, 0.1 * i as f32 / 10.0, 2, 30.0, ms(t, i * 10));
+        }
+        g.finger_up(1, ms(t, 100), true);
+        let (first, _) = g.tick(ms(t, 116));
+        assert!(first > 0.0, "{}", first);
+        let (second, _) = g.tick(ms(t, 132));
+        assert!(second > 0.0 && second < first, "{} {}", first, second);
+        let (rest, _) = g.tick(ms(t, 5000));
+        assert!(rest > 0.0 && rest < 5.0, "{}", rest);
+        assert_eq!(g.tick(ms(t, 5016)), (0.0, false));
+    }
+
+    #[test]
+    fn no_kinetic_scroll_when_disabled_or_paused() {
+        let mut g = Gestures::default();
+        let t = Instant::now();
+        g.finger_down(1);
+        g.finger_down(2);
+        g.multi_gesture(0.0, 0.1, 2, 30.0, t);
+        g.multi_gesture(0.0, 0.2, 2, 30.0, ms(t, 10));
+        g.finger_up(1, ms(t, 20), false);
+        assert_eq!(g.tick(ms(t, 36)), (0.0, false));
+
+        g.finger_down(1);
+        g.multi_gesture(0.0, 0.1, 2, 30.0, ms(t, 100));
+        g.multi_gesture(0.0, 0.2, 2, 30.0, ms(t, 110));
+        g.finger_up(1, ms(t, 400), true);
+        assert_eq!(g.tick(ms(t, 416)), (0.0, false));
+        assert_eq!(g.fingers(), 1);
+    }
+}
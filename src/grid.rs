@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use crate::neovim_connector::{GridLine, GridScroll};
+use crate::pane::TextCell;
+
+pub type Grid = Vec<Vec<TextCell>>;
+
+/// The cells of every grid nvim draws, by grid id. Without `ext_multigrid` there is only the
+/// default grid, 1.
+#[derive(Default)]
+pub struct Grids {
+    grids: HashMap<i64, Grid>,
+}
+
+impl Grids {
+    pub fn get(&self, grid: i64) -> Option<&Grid> {
+        self.grids.get(&grid)
+    }
+
+    /// Resizes a grid, creating it if needed. Cells that still fit are kept, as nvim only
+    /// redraws what changed.
+    pub fn resize(&mut self, grid: i64, cols: i64, rows: i64) {
+        let cells = self.grids.entry(grid).or_default();
+        cells.resize(rows as usize, Vec::new());
+        for row in cells.iter_mut() {
+            row.resize(cols as usize, TextCell::new());
+        }
+    }
+
+    pub fn destroy(&mut self, grid: i64) {
+        self.grids.remove(&grid);
+    }
+
+    pub fn clear(&mut self, grid: i64) {
+        if let Some(cells) = self.grids.get_mut(&grid) {
+            for cell in cells.iter_mut().flatten() {
+                *cell = TextCell::new();
+            }
+        }
+    }
+
+    /// Applies a `grid_line` event. Cells without a highlight id repeat the previous one.
+    pub fn line(&mut self, lines: Vec<GridLine>) {
+        let mut last_hl_id = -1;
+        for line in lines {
+            let row = match self
+                .grids
+                .get_mut(&line.grid)
+                .and_then(|cells| cells.get_mut(line.row as usize))
+            {
+                Some(row) => row,
+                None => continue,
+            };
+            let mut col = line.col as usize;
+            for cell in line.cells {
+                if cell.highlight != -1 {
+                    last_hl_id = cell.highlight;
+                }
+                for _ in 0..cell.repeat {
+                    if let Some(target) = row.get_mut(col) {
+                        *target = TextCell {
+                            text: cell.text.clone(),
+                            hl_id: last_hl_id,
+                        };
+                    }
+                    col += 1;
+                }
+            }
+        }
+    }
+
+    /// Applies a `grid_scroll` event. Cells moved away from are cleared until nvim redraws
+    /// the rows scrolled in.
+    pub fn scroll(&mut self, e: &GridScroll) {
+        let cells = match self.grids.get_mut(&e.grid) {
+            Some(cells) => cells,
+            None => return,
+        };
+        let rows: Box<dyn Iterator<Item = i64>> = if e.rows > 0 {
+            Box::new(e.top..e.bot)
+        } else {
+            Box::new((e.top..e.bot).rev())
+        };
+        for y in rows {
+            let target = y - e.rows;
+            if target < e.top || target >= e.bot {
+                continue;
+            }
+            for x in e.left..e.right {
+                let cell = std::mem::replace(&mut cells[y as usize][x as usize], TextCell::new());
+                cells[target as usize][x as usize] = cell;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neovim_connector::GridCell;
+
+    fn cell(text: &str, highlight: i64, repeat: i64) -> GridCell {
+        GridCell {
+            text: text.into(),
+            highlight,
+            repeat,
+        }
+    }
+
+    fn row_text(grids: &Grids, grid: i64, row: usize) -> String {
+        grids.get(grid).unwrap()[row]
+            .iter()
+            .map(|c| c.text.as_str())
+            .collect()
+    }
+
+    fn column(grids: &Grids) -> Vec<String> {
+        (0..4).map(|row| row_text(grids, 2, row)).collect()
+    }
+
+    #[test]
+    fn lines_repeat_highlights() {
+        let mut grids = Grids::default();
+        grids.resize(2, 4, 1);
+        grids.line(vec![GridLine {
+            grid: 2,
+            row: 0,
+            col: 0,
+            cells: vec![cell("a", 3, 2), cell("b", -1, 1), cell("c", 4, 5)],
+        }]);
+        let row = &grids.get(2).unwrap()[0];
+        assert_eq!(row_text(&grids, 2, 0), "aabc");
+        assert_eq!(
+            row.iter().map(|c| c.hl_id).collect::<Vec<_>>(),
+            vec![3, 3, 3, 4]
+        );
+    }
+
+    #[test]
+    fn lines_on_unknown_grids_are_ignored() {
+        let mut grids = Grids::default();
+        grids.line(vec![GridLine {
+            grid: 5,
+            row: 0,
+            col: 0,
+            cells: vec![cell("a", 1, 1)],
+        }]);
+        assert!(grids.get(5).is_none());
+    }
+
+    #[test]
+    fn resize_keeps_cells() {
+        let mut grids = Grids::default();
+        grids.resize(1, 2, 1);
+        grids.line(vec![GridLine {
+            grid: 1,
+            row: 0,
+            col: 0,
+            cells: vec![cell("x", 1, 2)],
+        }]);
+        grids.resize(1, 3, 2);
+        assert_eq!(row_text(&grids, 1, 0), "xx ");
+        assert_eq!(row_text(&grids, 1, 1), "   ");
+        grids.clear(1);
+        assert_eq!(row_text(&grids, 1, 0), "   ");
+    }
+
+    #[test]
+    fn scroll() {
+        let mut grids = Grids::default();
+        grids.resize(2, 1, 4);
+        for (row, text) in ["a", "b", "c", "d"].iter().enumerate() {
+            grids.line(vec![GridLine {
+                grid: 2,
+                row: row as i64,
+                col: 0,
+                cells: vec![cell(text, 1, 1)],
+            }]);
+        }
+        let region = |rows| GridScroll {
+            grid: 2,
+            top: 0,
+            bot: 3,
+            left: 0,
+            right: 1,
+            rows,
+            cols: 0,
+        };
+        grids.scroll(&region(1));
+        assert_eq!(column(&grids), vec!["b", "c", " ", "d"]);
+        grids.scroll(&region(-1));
+        assert_eq!(column(&grids), vec![" ", "b", "c", "d"]);
+    }
+}
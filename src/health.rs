@@ -0,0 +1,141 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use neovim_lib::Value;
+
+use crate::config::config_path;
+
+/// The `:checkhealth nvim-sdl` module, installed into a runtime directory of its own.
+const HEALTH_LUA: &str = include_str!("../runtime/lua/nvim-sdl/health.lua");
+
+/// What `:checkhealth nvim-sdl` reports about the client. The UI keeps it up to date and the
+/// connector answers the `nvim_sdl_health` request with it.
+#[derive(Debug, Default)]
+pub struct HealthInfo {
+    pub font_path: Option<PathBuf>,
+    pub font_size: u16,
+    /// `(width, height)` in pixels.
+    pub cell_size: (u32, u32),
+    pub renderer: String,
+    pub video_driver: String,
+    /// Renderer pixels per window coordinate.
+    pub scale: f32,
+    /// Diagonal DPI of the display, if SDL knows it.
+    pub dpi: Option<f32>,
+    /// The `nvim_ui_attach` options nvim-sdl enabled.
+    pub ui_options: Vec<String>,
+}
+
+impl HealthInfo {
+    pub fn to_value(&self) -> Value {
+        let path_value = |path: Option<&Path>| -> Value {
+            path.map(|p| p.display().to_string())
+                .unwrap_or_default()
+                .into()
+        };
+        let config = config_path();
+        let ui_options = self
+            .ui_options
+            .iter()
+            .map(|option| Value::from(option.as_str()))
+            .collect::<Vec<Value>>();
+        let entries: Vec<(&str, Value)> = vec![
+            ("font_path", path_value(self.font_path.as_deref())),
+            ("font_size", self.font_size.into()),
+            ("cell_width", self.cell_size.0.into()),
+            ("cell_height", self.cell_size.1.into()),
+            ("renderer", self.renderer.as_str().into()),
+            ("video_driver", self.video_driver.as_str().into()),
+            ("scale", f64::from(self.scale).into()),
+            ("dpi", f64::from(self.dpi.unwrap_or(0.0)).into()),
+            ("ui_options", ui_options.into()),
+            ("config_path", path_value(config.as_deref())),
+            ("config_exists", config.is_some_and(|p| p.exists()).into()),
+        ];
+        Value::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        )
+    }
+}
+
+/// Returns `$XDG_DATA_HOME/nvim-sdl/runtime`, falling back to `~/.local/share`.
+fn runtime_dir() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("share"),
+    };
+    Some(dir.join("nvim-sdl").join("runtime"))
+}
+
+/// Writes the health module and returns the runtime directory to add to 'runtimepath'.
+pub fn install_runtime() -> Option<PathBuf> {
+    let dir = runtime_dir()?;
+    let module_dir = dir.join("lua").join("nvim-sdl");
+    let result = fs::create_dir_all(&module_dir)
+        .and_then(|_| fs::write(module_dir.join("health.lua"), HEALTH_LUA));
+    match result {
+        Ok(()) => Some(dir),
+        Err(e) => {
+            println!("Failed to install the health check: {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the Ex command that adds `dir` to 'runtimepath'.
+pub fn add_to_runtimepath(dir: &Path) -> String {
+    let mut command = String::from("set rtp+=");
+    for c in dir.display().to_string().chars() {
+        match c {
+            ' ' | '|' | '"' => command.push('\\'),
+            // The option value needs "\,", and :set takes a backslash off.
+            ',' => command.push_str("\\\\"),
+            _ => {}
+        }
+        command.push(c);
+    }
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtimepath_escapes() {
+        assert_eq!(
+            add_to_runtimepath(Path::new("/home/a b/x,y")),
+            "set rtp+=/home/a\\ b/x\\\\,y"
+        );
+    }
+
+    #[test]
+    fn health_value() {
+        let info = HealthInfo {
+            font_path: Some(PathBuf::from("/fonts/mono.ttf")),
+            font_size: 14,
+            ui_options: vec!["rgb".into()],
+            ..HealthInfo::default()
+        };
+        let value = info.to_value();
+        let get = |key: &str| {
+            value
+                .as_map()
+                .unwrap()
+                .iter()
+                .find(|(k, _)| k.as_str() == Some(key))
+                .map(|(_, v)| v.clone())
+                .unwrap()
+        };
+        assert_eq!(get("font_path").as_str(), Some("/fonts/mono.ttf"));
+        assert_eq!(get("font_size").as_u64(), Some(14));
+        assert_eq!(get("ui_options"), Value::Array(vec!["rgb".into()]));
+        assert_eq!(get("dpi").as_f64(), Some(0.0));
+    }
+}
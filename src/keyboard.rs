@@ -0,0 +1,671 @@
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use std::collections::HashMap;
+use std::iter;
+
+/// Keys that have a name in nvim's key notation and never produce text.
+const NAMED_KEYS: &[(Keycode, &str)] = &[
+    (Keycode::Backspace, "BS"),
+    (Keycode::Tab, "Tab"),
+    (Keycode::Return, "CR"),
+    (Keycode::Escape, "Esc"),
+    (Keycode::Delete, "Del"),
+    (Keycode::F1, "F1"),
+    (Keycode::F2, "F2"),
+    (Keycode::F3, "F3"),
+    (Keycode::F4, "F4"),
+    (Keycode::F5, "F5"),
+    (Keycode::F6, "F6"),
+    (Keycode::F7, "F7"),
+    (Keycode::F8, "F8"),
+    (Keycode::F9, "F9"),
+    (Keycode::F10, "F10"),
+    (Keycode::F11, "F11"),
+    (Keycode::F12, "F12"),
+    (Keycode::F13, "F13"),
+    (Keycode::F14, "F14"),
+    (Keycode::F15, "F15"),
+    (Keycode::F16, "F16"),
+    (Keycode::F17, "F17"),
+    (Keycode::F18, "F18"),
+    (Keycode::F19, "F19"),
+    (Keycode::F20, "F20"),
+    (Keycode::F21, "F21"),
+    (Keycode::F22, "F22"),
+    (Keycode::F23, "F23"),
+    (Keycode::F24, "F24"),
+    (Keycode::Insert, "Insert"),
+    (Keycode::Home, "Home"),
+    (Keycode::PageUp, "PageUp"),
+    (Keycode::End, "End"),
+    (Keycode::PageDown, "PageDown"),
+    (Keycode::Right, "Right"),
+    (Keycode::Left, "Left"),
+    (Keycode::Down, "Down"),
+    (Keycode::Up, "Up"),
+    (Keycode::Help, "Help"),
+    (Keycode::Undo, "Undo"),
+];
+
+/// Keypad keys: the name used with NumLock on, the name used with NumLock off, and whether the
+/// key produces a `TextInput` event with NumLock on.
+const KEYPAD_KEYS: &[(Keycode, &str, &str, bool)] = &[
+    (Keycode::KpDivide, "kDivide", "kDivide", true),
+    (Keycode::KpMultiply, "kMultiply", "kMultiply", true),
+    (Keycode::KpMinus, "kMinus", "kMinus", true),
+    (Keycode::KpPlus, "kPlus", "kPlus", true),
+    (Keycode::KpEnter, "kEnter", "kEnter", false),
+    (Keycode::KpPeriod, "kPoint", "kDel", true),
+    (Keycode::Kp0, "k0", "kInsert", true),
+    (Keycode::Kp1, "k1", "kEnd", true),
+    (Keycode::Kp2, "k2", "kDown", true),
+    (Keycode::Kp3, "k3", "kPageDown", true),
+    (Keycode::Kp4, "k4", "kLeft", true),
+    (Keycode::Kp5, "k5", "kOrigin", true),
+    (Keycode::Kp6, "k6", "kRight", true),
+    (Keycode::Kp7, "k7", "kHome", true),
+    (Keycode::Kp8, "k8", "kUp", true),
+    (Keycode::Kp9, "k9", "kPageUp", true),
+];
+
+/// Characters produced by shift on a US keyboard, used for chords where no text is available.
+const US_SHIFTED: &[(char, char)] = &[
+    ('0', ')'),
+    ('1', '!'),
+    ('2', '@'),
+    ('3', '#'),
+    ('4', '$'),
+    ('5', '%'),
+    ('6', '^'),
+    ('7', '&'),
+    ('8', '*'),
+    ('9', '('),
+    (',', '<'),
+    ('-', '_'),
+    ('.', '>'),
+    ('/', '?'),
+    (';', ':'),
+    ('=', '+'),
+    ('[', '{'),
+    ('\\', '|'),
+    (']', '}'),
+    ('`', '~'),
+    ('\'', '"'),
+];
+
+/// Characters that have to be spelled out by name inside `<...>`.
+const CHAR_NAMES: &[(char, &str)] = &[(' ', "Space"), ('<', "lt"), ('\\', "Bslash"), ('|', "Bar")];
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct Modifiers {
+    shift: bool,
+    ctrl: bool,
+    alt: bool,
+    gui: bool,
+    num_lock: bool,
+}
+
+impl Modifiers {
+    fn from_keymod(keymod: Mod) -> Self {
+        Self {
+            shift: keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD),
+            ctrl: keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD),
+            alt: keymod.intersects(Mod::LALTMOD | Mod::RALTMOD),
+            gui: keymod.intersects(Mod::LGUIMOD | Mod::RGUIMOD),
+            num_lock: keymod.contains(Mod::NUMMOD),
+        }
+    }
+
+    /// Whether the key should be sent as a chord instead of waiting for its text.
+    fn is_chord(self) -> bool {
+        self.ctrl || self.alt || self.gui
+    }
+
+    fn prefix(self, with_shift: bool) -> String {
+        format!(
+            "{}{}{}{}",
+            if self.gui { "D-" } else { "" },
+            if self.alt { "M-" } else { "" },
+            if self.ctrl { "C-" } else { "" },
+            if with_shift && self.shift { "S-" } else { "" },
+        )
+    }
+}
+
+fn named_key(keycode: Keycode) -> Option<&'static str> {
+    NAMED_KEYS
+        .iter()
+        .find(|(kc, _)| *kc == keycode)
+        .map(|(_, name)| *name)
+}
+
+/// Returns the unshifted character of a printable key.
+fn printable_char(keycode: Keycode) -> Option<char> {
+    let code = keycode as i32;
+    if (0x20..0x7f).contains(&code) {
+        Some(code as u8 as char)
+    } else {
+        None
+    }
+}
+
+fn us_shifted(c: char) -> char {
+    if c.is_ascii_lowercase() {
+        return c.to_ascii_uppercase();
+    }
+    US_SHIFTED
+        .iter()
+        .find(|(base, _)| *base == c)
+        .map(|(_, shifted)| *shifted)
+        .unwrap_or(c)
+}
+
+/// Returns how `translate` spells a key name given in any case, e.g. `Space` for `space`.
+pub fn key_name(name: &str) -> Option<&'static str> {
+    let keypad = KEYPAD_KEYS
+        .iter()
+        .flat_map(|&(_, num_name, nav_name, _)| iter::once(num_name).chain(iter::once(nav_name)));
+    NAMED_KEYS
+        .iter()
+        .map(|(_, name)| *name)
+        .chain(keypad)
+        .chain(CHAR_NAMES.iter().map(|(_, name)| *name))
+        .find(|known| known.eq_ignore_ascii_case(name))
+}
+
+fn char_name(c: char) -> String {
+    CHAR_NAMES
+        .iter()
+        .find(|(named, _)| *named == c)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| c.to_string())
+}
+
+/// Escapes text produced by a `TextInput` event so it can be passed to `nvim_input`.
+pub fn escape_text(text: &str) -> String {
+    text.replace("<", "<lt>")
+}
+
+/// Translates a key press into nvim key notation.
+///
+/// `text` is the text SDL produced for the key press, if it is known. Returns `None` when the
+/// key doesn't map to any nvim input, or when it should be sent by its `TextInput` event instead.
+pub fn translate(keycode: Keycode, keymod: Mod, text: Option<&str>) -> Option<String> {
+    let mods = Modifiers::from_keymod(keymod);
+
+    if let Some(name) = named_key(keycode) {
+        return Some(format!("<{}{}>", mods.prefix(true), name));
+    }
+
+    if let Some(&(_, num_name, nav_name, has_text)) =
+        KEYPAD_KEYS.iter().find(|(kc, ..)| *kc == keycode)
+    {
+        let name = if mods.num_lock { num_name } else { nav_name };
+        let has_text = has_text && (mods.num_lock || num_name == nav_name);
+        if has_text && !mods.is_chord() {
+            return text.map(escape_text);
+        }
+        return Some(format!("<{}{}>", mods.prefix(true), name));
+    }
+
+    let base = printable_char(keycode)?;
+    if !mods.is_chord() {
+        return text.map(escape_text);
+    }
+    if base == ' ' {
+        return Some(format!("<{}Space>", mods.prefix(true)));
+    }
+    let c = match text.and_then(|t| t.chars().next()) {
+        Some(c) => c,
+        None if mods.shift => us_shifted(base),
+        None => base,
+    };
+    Some(char_chord(mods, c))
+}
+
+fn char_chord(mods: Modifiers, c: char) -> String {
+    // nvim doesn't distinguish <C-a> from <C-A>, so shift has to be spelled out for letters.
+    let with_shift = mods.ctrl && c.is_alphabetic();
+    let c = if with_shift && mods.shift {
+        c.to_uppercase().next().unwrap_or(c)
+    } else {
+        c
+    };
+    format!("<{}{}>", mods.prefix(with_shift), char_name(c))
+}
+
+struct PendingChord {
+    chord: String,
+    /// The text the key produces without AltGr, if known.
+    plain_text: Option<String>,
+}
+
+/// Pairs SDL's `KeyDown` and `TextInput` events, learning the active keyboard layout from them.
+///
+/// SDL's keycodes only describe the unshifted character of a key, so the text each key produces
+/// is remembered by scancode and used for later Ctrl/Alt chords on the same key.
+pub struct Keyboard {
+    layout: HashMap<(Scancode, bool), String>,
+    /// The last printable key, still waiting for its text.
+    last_key: Option<(Scancode, bool)>,
+    /// A chord that might turn out to be an AltGr character.
+    pending: Option<PendingChord>,
+    suppress_text: bool,
+    composing: bool,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self {
+            layout: HashMap::new(),
+            last_key: None,
+            pending: None,
+            suppress_text: false,
+            composing: false,
+        }
+    }
+
+    /// Handles a `KeyDown` event, returning the input to send to nvim, if any.
+    pub fn key_down(
+        &mut self,
+        keycode: Option<Keycode>,
+        scancode: Option<Scancode>,
+        keymod: Mod,
+    ) -> Option<String> {
+        let flushed = self.flush();
+        self.suppress_text = false;
+        if self.composing {
+            return flushed;
+        }
+        let mods = Modifiers::from_keymod(keymod);
+        let learned = scancode.and_then(|sc| self.layout.get(&(sc, mods.shift)).cloned());
+
+        let printable = match keycode {
+            Some(kc) => printable_char(kc).is_some(),
+            None => true,
+        };
+        if printable && !mods.is_chord() {
+            // A printable key that never produced text was a dead key, so the next text is a
+            // composed character and says nothing about the layout.
+            let after_dead_key = self.last_key.take().is_some();
+            if !after_dead_key && !keymod.contains(Mod::CAPSMOD) {
+                self.last_key = scancode.map(|sc| (sc, mods.shift));
+            }
+        }
+
+        let key = match keycode {
+            Some(kc) if mods.is_chord() => translate(kc, keymod, learned.as_deref()),
+            Some(kc) => translate(kc, keymod, None),
+            None if mods.is_chord() => learned
+                .as_ref()
+                .and_then(|t| t.chars().next())
+                .map(|c| char_chord(mods, c)),
+            None => None,
+        };
+        let key = match key {
+            Some(key) if printable && mods.is_chord() => key,
+            key => return join(flushed, key),
+        };
+
+        if keymod.intersects(Mod::RALTMOD | Mod::MODEMOD) {
+            let plain_text = learned.or_else(|| {
+                keycode
+                    .and_then(printable_char)
+                    .map(|c| if mods.shift { us_shifted(c) } else { c }.to_string())
+            });
+            self.pending = Some(PendingChord {
+                chord: key,
+                plain_text,
+            });
+            flushed
+        } else {
+            // Some platforms still send the text of an Alt chord, which must not be typed too.
+            self.suppress_text = true;
+            join(flushed, Some(key))
+        }
+    }
+
+    /// Handles a `TextInput` event, returning the input to send to nvim, if any.
+    pub fn text_input(&mut self, text: &str) -> Option<String> {
+        self.composing = false;
+        if let Some(pending) = self.pending.take() {
+            if pending.plain_text.as_deref() == Some(text) {
+                // Right Alt is a plain Alt key on this layout.
+                return Some(pending.chord);
+            }
+            // AltGr produced a character of its own, so it is text rather than a chord.
+            return Some(escape_text(text));
+        }
+        if self.suppress_text {
+            self.suppress_text = false;
+            return None;
+        }
+        if let Some(key) = self.last_key.take() {
+            self.layout.insert(key, text.to_string());
+        }
+        Some(escape_text(text))
+    }
+
+    /// Handles a `TextEditing` event, which is sent while a dead key or input method composes.
+    pub fn text_editing(&mut self, text: &str) {
+        self.composing = !text.is_empty();
+        self.last_key = None;
+    }
+
+    /// Returns a chord that is no longer waiting for text. Call after each batch of events.
+    pub fn flush(&mut self) -> Option<String> {
+        self.pending.take().map(|pending| pending.chord)
+    }
+}
+
+fn join(a: Option<String>, b: Option<String>) -> Option<String> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a + &b),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONE: Mod = Mod::NOMOD;
+    const SHIFT: Mod = Mod::LSHIFTMOD;
+    const CTRL: Mod = Mod::LCTRLMOD;
+    const ALT: Mod = Mod::LALTMOD;
+    const GUI: Mod = Mod::LGUIMOD;
+
+    fn key(keycode: Keycode, keymod: Mod) -> Option<String> {
+        translate(keycode, keymod, None)
+    }
+
+    #[test]
+    fn plain_printable_keys_wait_for_text() {
+        for c in (b' '..b'@').chain(b'['..b'a') {
+            let kc = Keycode::from_i32(c as i32).unwrap();
+            assert_eq!(key(kc, NONE), None, "{:?}", kc);
+            assert_eq!(key(kc, SHIFT), None, "{:?}", kc);
+            assert_eq!(key(kc, Mod::CAPSMOD), None, "{:?}", kc);
+        }
+        for c in b'a'..=b'z' {
+            let kc = Keycode::from_i32(c as i32).unwrap();
+            assert_eq!(key(kc, NONE), None, "{:?}", kc);
+            assert_eq!(key(kc, SHIFT), None, "{:?}", kc);
+        }
+    }
+
+    #[test]
+    fn plain_printable_keys_with_text() {
+        assert_eq!(translate(Keycode::A, NONE, Some("a")), Some("a".into()));
+        assert_eq!(
+            translate(Keycode::Comma, SHIFT, Some("<")),
+            Some("<lt>".into())
+        );
+        assert_eq!(
+            translate(Keycode::Backslash, NONE, Some("\\")),
+            Some("\\".into())
+        );
+    }
+
+    #[test]
+    fn escape_text_escapes_lt() {
+        assert_eq!(escape_text("a<b"), "a<lt>b");
+        assert_eq!(escape_text("\\|"), "\\|");
+    }
+
+    #[test]
+    fn named_keys() {
+        for &(kc, name) in NAMED_KEYS {
+            assert_eq!(key(kc, NONE), Some(format!("<{}>", name)));
+            assert_eq!(key(kc, SHIFT), Some(format!("<S-{}>", name)));
+            assert_eq!(key(kc, CTRL), Some(format!("<C-{}>", name)));
+            assert_eq!(key(kc, ALT), Some(format!("<M-{}>", name)));
+            assert_eq!(
+                key(kc, ALT | CTRL | SHIFT),
+                Some(format!("<M-C-S-{}>", name))
+            );
+        }
+        assert_eq!(key(Keycode::Tab, Mod::RSHIFTMOD), Some("<S-Tab>".into()));
+    }
+
+    #[test]
+    fn space_chords() {
+        assert_eq!(key(Keycode::Space, NONE), None);
+        assert_eq!(key(Keycode::Space, CTRL), Some("<C-Space>".into()));
+        assert_eq!(key(Keycode::Space, ALT), Some("<M-Space>".into()));
+        assert_eq!(
+            key(Keycode::Space, CTRL | SHIFT),
+            Some("<C-S-Space>".into())
+        );
+    }
+
+    #[test]
+    fn letter_chords() {
+        assert_eq!(key(Keycode::A, CTRL), Some("<C-a>".into()));
+        assert_eq!(key(Keycode::A, Mod::RCTRLMOD), Some("<C-a>".into()));
+        assert_eq!(key(Keycode::A, CTRL | SHIFT), Some("<C-S-A>".into()));
+        assert_eq!(key(Keycode::Z, ALT), Some("<M-z>".into()));
+        assert_eq!(key(Keycode::Z, ALT | SHIFT), Some("<M-Z>".into()));
+        assert_eq!(key(Keycode::Z, ALT | CTRL), Some("<M-C-z>".into()));
+        assert_eq!(
+            key(Keycode::Z, ALT | CTRL | SHIFT),
+            Some("<M-C-S-Z>".into())
+        );
+    }
+
+    #[test]
+    fn super_chords() {
+        assert_eq!(key(Keycode::S, GUI), Some("<D-s>".into()));
+        assert_eq!(key(Keycode::S, Mod::RGUIMOD), Some("<D-s>".into()));
+        assert_eq!(key(Keycode::S, GUI | SHIFT), Some("<D-S>".into()));
+        assert_eq!(
+            key(Keycode::S, GUI | CTRL | SHIFT),
+            Some("<D-C-S-S>".into())
+        );
+        assert_eq!(key(Keycode::Equals, GUI), Some("<D-=>".into()));
+        assert_eq!(key(Keycode::Space, GUI), Some("<D-Space>".into()));
+        assert_eq!(key(Keycode::Kp1, GUI | Mod::NUMMOD), Some("<D-k1>".into()));
+    }
+
+    #[test]
+    fn symbol_chords() {
+        assert_eq!(key(Keycode::Num1, CTRL), Some("<C-1>".into()));
+        assert_eq!(key(Keycode::Num1, CTRL | SHIFT), Some("<C-!>".into()));
+        assert_eq!(key(Keycode::Slash, ALT | SHIFT), Some("<M-?>".into()));
+        assert_eq!(key(Keycode::LeftBracket, CTRL), Some("<C-[>".into()));
+        assert_eq!(key(Keycode::Equals, CTRL | SHIFT), Some("<C-+>".into()));
+        for &(base, shifted) in US_SHIFTED {
+            let kc = Keycode::from_i32(base as i32).unwrap();
+            let expected = char_name(shifted);
+            assert_eq!(key(kc, ALT | SHIFT), Some(format!("<M-{}>", expected)));
+        }
+    }
+
+    #[test]
+    fn special_characters_in_chords() {
+        assert_eq!(key(Keycode::Comma, CTRL | SHIFT), Some("<C-lt>".into()));
+        assert_eq!(key(Keycode::Backslash, CTRL), Some("<C-Bslash>".into()));
+        assert_eq!(key(Keycode::Backslash, ALT | SHIFT), Some("<M-Bar>".into()));
+        assert_eq!(key(Keycode::Less, ALT), Some("<M-lt>".into()));
+    }
+
+    #[test]
+    fn chords_prefer_text() {
+        assert_eq!(
+            translate(Keycode::Num7, CTRL | SHIFT, Some("/")),
+            Some("<C-/>".into())
+        );
+        assert_eq!(
+            translate(Keycode::Comma, ALT, Some("<")),
+            Some("<M-lt>".into())
+        );
+    }
+
+    #[test]
+    fn keypad_with_num_lock() {
+        let num = Mod::NUMMOD;
+        for &(kc, name, _, has_text) in KEYPAD_KEYS {
+            if has_text {
+                assert_eq!(key(kc, num), None, "{:?}", kc);
+            } else {
+                assert_eq!(key(kc, num), Some(format!("<{}>", name)));
+            }
+            assert_eq!(key(kc, num | CTRL), Some(format!("<C-{}>", name)));
+            assert_eq!(key(kc, num | ALT | SHIFT), Some(format!("<M-S-{}>", name)));
+        }
+        assert_eq!(translate(Keycode::Kp1, num, Some("1")), Some("1".into()));
+    }
+
+    #[test]
+    fn keypad_without_num_lock() {
+        assert_eq!(key(Keycode::Kp1, NONE), Some("<kEnd>".into()));
+        assert_eq!(key(Keycode::Kp5, NONE), Some("<kOrigin>".into()));
+        assert_eq!(key(Keycode::Kp8, SHIFT), Some("<S-kUp>".into()));
+        assert_eq!(key(Keycode::KpPeriod, CTRL), Some("<C-kDel>".into()));
+        assert_eq!(key(Keycode::KpEnter, NONE), Some("<kEnter>".into()));
+        assert_eq!(key(Keycode::KpPlus, NONE), None);
+        assert_eq!(key(Keycode::KpPlus, CTRL), Some("<C-kPlus>".into()));
+    }
+
+    #[test]
+    fn keys_without_notation() {
+        assert_eq!(key(Keycode::CapsLock, NONE), None);
+        assert_eq!(key(Keycode::LShift, SHIFT), None);
+        assert_eq!(key(Keycode::LCtrl, CTRL), None);
+        assert_eq!(key(Keycode::PrintScreen, CTRL), None);
+    }
+
+    fn press(kb: &mut Keyboard, kc: Option<Keycode>, sc: Scancode, keymod: Mod) -> Option<String> {
+        kb.key_down(kc, Some(sc), keymod)
+    }
+
+    #[test]
+    fn keyboard_learns_shifted_symbols() {
+        // German layout: shift+7 is '/'.
+        let mut kb = Keyboard::new();
+        assert_eq!(
+            press(&mut kb, Some(Keycode::Num7), Scancode::Num7, SHIFT),
+            None
+        );
+        assert_eq!(kb.text_input("/"), Some("/".into()));
+        assert_eq!(
+            press(&mut kb, Some(Keycode::Num7), Scancode::Num7, SHIFT | CTRL),
+            Some("<C-/>".into())
+        );
+        assert_eq!(kb.text_input("/"), None);
+        assert_eq!(
+            press(&mut kb, Some(Keycode::Num7), Scancode::Num7, CTRL),
+            Some("<C-7>".into())
+        );
+    }
+
+    #[test]
+    fn keyboard_chords_for_keys_without_keycode() {
+        // German layout: the key right of L is 'ö', which has no SDL keycode of its own.
+        let mut kb = Keyboard::new();
+        assert_eq!(press(&mut kb, None, Scancode::Semicolon, NONE), None);
+        assert_eq!(kb.text_input("ö"), Some("ö".into()));
+        assert_eq!(
+            press(&mut kb, None, Scancode::Semicolon, ALT),
+            Some("<M-ö>".into())
+        );
+        assert_eq!(press(&mut kb, None, Scancode::Apostrophe, ALT), None);
+    }
+
+    #[test]
+    fn keyboard_ignores_text_of_alt_chords() {
+        let mut kb = Keyboard::new();
+        assert_eq!(
+            press(&mut kb, Some(Keycode::A), Scancode::A, ALT),
+            Some("<M-a>".into())
+        );
+        assert_eq!(kb.text_input("a"), None);
+        assert_eq!(kb.flush(), None);
+        assert_eq!(press(&mut kb, Some(Keycode::A), Scancode::A, NONE), None);
+        assert_eq!(kb.text_input("a"), Some("a".into()));
+    }
+
+    #[test]
+    fn keyboard_treats_altgr_as_text() {
+        // German layout: AltGr+q is '@'.
+        let mut kb = Keyboard::new();
+        assert_eq!(
+            press(&mut kb, Some(Keycode::Q), Scancode::Q, Mod::RALTMOD),
+            None
+        );
+        assert_eq!(kb.text_input("@"), Some("@".into()));
+        assert_eq!(kb.flush(), None);
+
+        // Windows reports AltGr as Ctrl+Alt.
+        let altgr = Mod::LCTRLMOD | Mod::RALTMOD;
+        assert_eq!(
+            press(&mut kb, Some(Keycode::Num2), Scancode::Num2, altgr),
+            None
+        );
+        assert_eq!(kb.text_input("²"), Some("²".into()));
+
+        assert_eq!(
+            press(
+                &mut kb,
+                Some(Keycode::Comma),
+                Scancode::NonUsBackslash,
+                Mod::MODEMOD
+            ),
+            None
+        );
+        assert_eq!(kb.text_input("<"), Some("<lt>".into()));
+    }
+
+    #[test]
+    fn keyboard_right_alt_without_altgr() {
+        let mut kb = Keyboard::new();
+        assert_eq!(
+            press(&mut kb, Some(Keycode::X), Scancode::X, Mod::RALTMOD),
+            None
+        );
+        assert_eq!(kb.text_input("x"), Some("<M-x>".into()));
+
+        assert_eq!(
+            press(&mut kb, Some(Keycode::X), Scancode::X, Mod::RALTMOD),
+            None
+        );
+        assert_eq!(kb.flush(), Some("<M-x>".into()));
+
+        assert_eq!(
+            press(&mut kb, Some(Keycode::X), Scancode::X, Mod::RALTMOD),
+            None
+        );
+        assert_eq!(
+            press(&mut kb, Some(Keycode::Y), Scancode::Y, Mod::RALTMOD),
+            Some("<M-x>".into())
+        );
+    }
+
+    #[test]
+    fn keyboard_dead_keys() {
+        let mut kb = Keyboard::new();
+        // Dead acute, then e.
+        assert_eq!(
+            press(&mut kb, Some(Keycode::Equals), Scancode::Equals, NONE),
+            None
+        );
+        assert_eq!(press(&mut kb, Some(Keycode::E), Scancode::E, NONE), None);
+        assert_eq!(kb.text_input("é"), Some("é".into()));
+        assert_eq!(
+            press(&mut kb, Some(Keycode::E), Scancode::E, CTRL),
+            Some("<C-e>".into())
+        );
+
+        // Dead circumflex composed through an input method.
+        kb.text_editing("^");
+        assert_eq!(
+            press(&mut kb, Some(Keycode::Return), Scancode::Return, NONE),
+            None
+        );
+        assert_eq!(press(&mut kb, Some(Keycode::A), Scancode::A, NONE), None);
+        assert_eq!(kb.text_input("â"), Some("â".into()));
+        assert_eq!(
+            press(&mut kb, Some(Keycode::Return), Scancode::Return, NONE),
+            Some("<CR>".into())
+        );
+    }
+}
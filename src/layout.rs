@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+
+use crate::neovim_connector::WinFloatPos;
+
+/// Position of a grid on the screen, in cells.
+#[derive(Debug, Clone, PartialEq)]
+struct GridPos {
+    grid: i64,
+    row: i64,
+    col: i64,
+    width: i64,
+    height: i64,
+    zindex: i64,
+    focusable: bool,
+}
+
+impl GridPos {
+    fn contains(&self, row: i64, col: i64) -> bool {
+        row >= self.row
+            && row < self.row + self.height
+            && col >= self.col
+            && col < self.col + self.width
+    }
+}
+
+/// What is on the screen at a position, as far as the pointer shape is concerned.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Region {
+    Text,
+    /// A statusline, which can be dragged to resize the window above it.
+    StatusLine,
+    /// A vertical separator, which can be dragged to resize the window to its left.
+    Separator,
+    /// Past the last row or column of the grid.
+    Outside,
+}
+
+impl Region {
+    /// The region a cell is in going by its highlight's UI groups, from `ext_hlstate`. Unlike
+    /// window positions, these are known without multigrid.
+    pub fn from_ui_groups(groups: &[String]) -> Option<Region> {
+        groups.iter().find_map(|group| match group.as_str() {
+            "StatusLine" | "StatusLineNC" | "StatusLineTerm" | "StatusLineTermNC" => {
+                Some(Region::StatusLine)
+            }
+            "VertSplit" | "WinSeparator" => Some(Region::Separator),
+            _ => None,
+        })
+    }
+}
+
+/// Tracks where nvim places each grid when `ext_multigrid` is enabled, so screen positions can
+/// be mapped back to a grid and a position inside it.
+///
+/// Without multigrid, no windows are ever positioned and everything maps to grid 0, which
+/// `nvim_input_mouse` takes to mean the single global grid.
+#[derive(Default)]
+pub struct Layout {
+    sizes: HashMap<i64, (i64, i64)>,
+    /// Visible grids, bottom to top.
+    windows: Vec<GridPos>,
+}
+
+/// The grid that covers the whole screen, below all windows. `nvim_input_mouse` uses it for
+/// positions outside all windows.
+pub const DEFAULT_GRID: i64 = 1;
+
+impl Layout {
+    pub fn grid_resize(&mut self, grid: i64, cols: i64, rows: i64) {
+        self.sizes.insert(grid, (cols, rows));
+        for pos in self.windows.iter_mut().filter(|pos| pos.grid == grid) {
+            pos.width = cols;
+            pos.height = rows;
+        }
+    }
+
+    pub fn win_pos(&mut self, grid: i64, row: i64, col: i64, width: i64, height: i64) {
+        self.place(GridPos {
+            grid,
+            row,
+            col,
+            width,
+            height,
+            zindex: 0,
+            focusable: true,
+        });
+    }
+
+    /// Positions a floating window relative to its anchor grid.
+    pub fn win_float_pos(&mut self, float: &WinFloatPos) {
+        let (width, height) = self.sizes.get(&float.grid).cloned().unwrap_or((0, 0));
+        let (origin_row, origin_col) = self
+            .windows
+            .iter()
+            .find(|pos| pos.grid == float.anchor_grid)
+            .map(|pos| (pos.row, pos.col))
+            .unwrap_or((0, 0));
+        // The anchor names the corner of the float that is placed at the anchor position.
+        let mut row = origin_row + float.anchor_row.round() as i64;
+        let mut col = origin_col + float.anchor_col.round() as i64;
+        if float.anchor.starts_with('S') {
+            row -= height;
+        }
+        if float.anchor.ends_with('E') {
+            col -= width;
+        }
+        self.place(GridPos {
+            grid: float.grid,
+            row,
+            col,
+            width,
+            height,
+            zindex: float.zindex,
+            focusable: float.focusable,
+        });
+    }
+
+    /// Positions the message grid, which spans the whole screen width from `row` down.
+    pub fn msg_set_pos(&mut self, grid: i64, row: i64) {
+        let (width, height) = self.sizes.get(&DEFAULT_GRID).cloned().unwrap_or((0, 0));
+        self.place(GridPos {
+            grid,
+            row,
+            col: 0,
+            width,
+            height: height - row,
+            zindex: i64::MAX,
+            focusable: true,
+        });
+    }
+
+    pub fn win_hide(&mut self, grid: i64) {
+        self.windows.retain(|pos| pos.grid != grid);
+    }
+
+    pub fn grid_destroy(&mut self, grid: i64) {
+        self.win_hide(grid);
+        self.sizes.remove(&grid);
+    }
+
+    fn place(&mut self, pos: GridPos) {
+        self.windows.retain(|p| p.grid != pos.grid);
+        // Keep the list sorted by z-index; windows with equal z-index stack in placement order.
+        let i = self
+            .windows
+            .iter()
+            .position(|p| p.zindex > pos.zindex)
+            .unwrap_or(self.windows.len());
+        self.windows.insert(i, pos);
+    }
+
+    /// Returns the grid at a screen position, and the position relative to that grid.
+    pub fn grid_at(&self, row: i64, col: i64) -> (i64, i64, i64) {
+        if self.windows.is_empty() {
+            return (0, row, col);
+        }
+        match self
+            .windows
+            .iter()
+            .rev()
+            .find(|pos| pos.focusable && pos.contains(row, col))
+        {
+            Some(pos) => (pos.grid, row - pos.row, col - pos.col),
+            None => (DEFAULT_GRID, row, col),
+        }
+    }
+
+    /// Returns what to draw, bottom to top, with the screen position of its top left cell: the
+    /// default grid, then the visible windows. `get` looks up a grid's contents; grids it
+    /// doesn't have are left out.
+    pub fn layers<T>(&self, get: impl Fn(i64) -> Option<T>) -> Vec<(i64, i64, T)> {
+        let default = get(DEFAULT_GRID).map(|grid| (0, 0, grid));
+        let windows = self
+            .windows
+            .iter()
+            .filter_map(|pos| Some((pos.row, pos.col, get(pos.grid)?)));
+        default.into_iter().chain(windows).collect()
+    }
+
+    /// Returns the screen position of a grid's top left cell.
+    pub fn origin(&self, grid: i64) -> (i64, i64) {
+        self.windows
+            .iter()
+            .find(|pos| pos.grid == grid)
+            .map_or((0, 0), |pos| (pos.row, pos.col))
+    }
+
+    /// Returns what is at a screen position. `cell` is the region the default grid's cell there
+    /// is highlighted as, if any. Without it, statuslines and separators are found as the cells
+    /// just below and right of a window that no window covers, which needs multigrid.
+    pub fn region_at(&self, row: i64, col: i64, cell: Option<Region>) -> Region {
+        if let Some(&(cols, rows)) = self.sizes.get(&DEFAULT_GRID) {
+            if row >= rows || col >= cols {
+                return Region::Outside;
+            }
+        }
+        if self.windows.iter().any(|pos| pos.contains(row, col)) {
+            return Region::Text;
+        }
+        if let Some(region) = cell {
+            return region;
+        }
+        for pos in self.windows.iter().filter(|pos| pos.zindex == 0) {
+            let below = row == pos.row + pos.height && col >= pos.col && col <= pos.col + pos.width;
+            if below {
+                return Region::StatusLine;
+            }
+            let beside = col == pos.col + pos.width && row >= pos.row && row < pos.row + pos.height;
+            if beside {
+                return Region::Separator;
+            }
+        }
+        Region::Text
+    }
+
+    /// Returns a screen position relative to `grid`, e.g. to keep dragging on the grid where
+    /// the drag started even after the pointer has left it.
+    pub fn relative_to(&self, grid: i64, row: i64, col: i64) -> (i64, i64, i64) {
+        match self.windows.iter().find(|pos| pos.grid == grid) {
+            Some(pos) => (grid, row - pos.row, col - pos.col),
+            None => (grid, row, col),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split_layout() -> Layout {
+        let mut layout = Layout::default();
+        layout.grid_resize(1, 80, 24);
+        layout.grid_resize(2, 40, 22);
+        layout.grid_resize(3, 39, 22);
+        layout.win_pos(2, 0, 0, 40, 22);
+        layout.win_pos(3, 0, 41, 39, 22);
+        layout
+    }
+
+    #[test]
+    fn without_multigrid_everything_is_grid_0() {
+        let layout = Layout::default();
+        assert_eq!(layout.grid_at(5, 7), (0, 5, 7));
+        assert_eq!(layout.relative_to(0, 5, 7), (0, 5, 7));
+    }
+
+    #[test]
+    fn windows_map_to_local_positions() {
+        let layout = split_layout();
+        assert_eq!(layout.grid_at(0, 0), (2, 0, 0));
+        assert_eq!(layout.grid_at(10, 45), (3, 10, 4));
+        // The vertical separator and the command line belong to the default grid.
+        assert_eq!(layout.grid_at(10, 40), (1, 10, 40));
+        assert_eq!(layout.grid_at(23, 3), (1, 23, 3));
+    }
+
+    #[test]
+    fn regions() {
+        let layout = split_layout();
+        assert_eq!(layout.region_at(3, 3, None), Region::Text);
+        assert_eq!(layout.region_at(3, 40, None), Region::Separator);
+        assert_eq!(layout.region_at(22, 10, None), Region::StatusLine);
+        assert_eq!(layout.region_at(22, 40, None), Region::StatusLine);
+        assert_eq!(layout.region_at(22, 60, None), Region::StatusLine);
+        assert_eq!(layout.region_at(23, 10, None), Region::Text);
+        assert_eq!(layout.region_at(24, 10, None), Region::Outside);
+        assert_eq!(layout.region_at(3, 80, None), Region::Outside);
+
+        let mut layout = Layout::default();
+        assert_eq!(layout.region_at(22, 10, None), Region::Text);
+        layout.grid_resize(1, 80, 24);
+        assert_eq!(layout.region_at(22, 10, None), Region::Text);
+        assert_eq!(layout.region_at(30, 10, None), Region::Outside);
+    }
+
+    #[test]
+    fn regions_from_highlights() {
+        let statusline = Region::from_ui_groups(&["StatusLineNC".into()]);
+        assert_eq!(statusline, Some(Region::StatusLine));
+        let separator = Region::from_ui_groups(&["Normal".into(), "WinSeparator".into()]);
+        assert_eq!(separator, Some(Region::Separator));
+        assert_eq!(Region::from_ui_groups(&["Visual".into()]), None);
+        assert_eq!(Region::from_ui_groups(&[]), None);
+
+        // Without multigrid only the highlights tell statuslines apart.
+        let mut layout = Layout::default();
+        layout.grid_resize(1, 80, 24);
+        assert_eq!(layout.region_at(22, 10, statusline), Region::StatusLine);
+        assert_eq!(layout.region_at(30, 10, statusline), Region::Outside);
+        // Windows drawn over the default grid win.
+        let layout = split_layout();
+        assert_eq!(layout.region_at(3, 3, separator), Region::Text);
+        assert_eq!(layout.region_at(3, 40, separator), Region::Separator);
+    }
+
+    #[test]
+    fn windows_and_origins() {
+        let mut layout = split_layout();
+        layout.grid_resize(4, 10, 3);
+        layout.win_float_pos(&float(4, "NW", 1.0, 2.0, true));
+        assert_eq!(
+            layout.layers(Some),
+            vec![(0, 0, 1), (0, 0, 2), (0, 41, 3), (1, 43, 4)]
+        );
+        assert_eq!(layout.origin(3), (0, 41));
+        assert_eq!(layout.origin(1), (0, 0));
+    }
+
+    #[test]
+    fn layers_stack_by_zindex() {
+        let mut layout = split_layout();
+        layout.grid_resize(4, 10, 3);
+        layout.grid_resize(5, 10, 3);
+        layout.win_float_pos(&WinFloatPos {
+            zindex: 100,
+            ..float(4, "NW", 1.0, 2.0, true)
+        });
+        layout.win_float_pos(&float(5, "NW", 5.0, 6.0, true));
+        // Floats above splits, higher z-index on top, even if positioned first.
+        assert_eq!(
+            layout.layers(Some),
+            vec![(0, 0, 1), (0, 0, 2), (0, 41, 3), (5, 47, 5), (1, 43, 4)]
+        );
+        // Moving a split keeps it below the floats.
+        layout.win_pos(2, 1, 0, 40, 21);
+        assert_eq!(
+            layout.layers(Some),
+            vec![(0, 0, 1), (0, 41, 3), (1, 0, 2), (5, 47, 5), (1, 43, 4)]
+        );
+        // Grids without contents are skipped.
+        let contents = |grid| if grid == 3 { None } else { Some(grid * 10) };
+        assert_eq!(
+            layout.layers(contents),
+            vec![(0, 0, 10), (1, 0, 20), (5, 47, 50), (1, 43, 40)]
+        );
+        layout.win_hide(4);
+        assert_eq!(layout.layers(Some).last(), Some(&(5, 47, 5)));
+    }
+
+    #[test]
+    fn drags_stay_on_their_grid() {
+        let layout = split_layout();
+        assert_eq!(layout.relative_to(3, 10, 30), (3, 10, -11));
+        assert_eq!(layout.relative_to(2, 23, 10), (2, 23, 10));
+    }
+
+    fn float(grid: i64, anchor: &str, row: f64, col: f64, focusable: bool) -> WinFloatPos {
+        WinFloatPos {
+            grid,
+            anchor: anchor.into(),
+            anchor_grid: 3,
+            anchor_row: row,
+            anchor_col: col,
+            focusable,
+            zindex: 50,
+        }
+    }
+
+    #[test]
+    fn floats_are_on_top() {
+        let mut layout = split_layout();
+        layout.grid_resize(4, 10, 3);
+        layout.win_float_pos(&float(4, "NW", 2.0, 5.0, true));
+        assert_eq!(layout.grid_at(2, 46), (4, 0, 0));
+        assert_eq!(layout.grid_at(4, 55), (4, 2, 9));
+        assert_eq!(layout.grid_at(5, 55), (3, 5, 14));
+
+        layout.win_float_pos(&float(4, "SE", 10.0, 20.0, true));
+        assert_eq!(layout.grid_at(7, 51), (4, 0, 0));
+
+        layout.win_float_pos(&float(4, "NW", 2.0, 5.0, false));
+        assert_eq!(layout.grid_at(2, 46), (3, 2, 5));
+
+        layout.win_hide(4);
+        layout.grid_resize(5, 5, 5);
+        layout.win_float_pos(&WinFloatPos {
+            anchor_grid: 1,
+            ..float(5, "NW", 0.0, 0.0, true)
+        });
+        assert_eq!(layout.grid_at(0, 0), (5, 0, 0));
+        layout.grid_destroy(5);
+        assert_eq!(layout.grid_at(0, 0), (2, 0, 0));
+    }
+
+    #[test]
+    fn message_grid() {
+        let mut layout = split_layout();
+        layout.grid_resize(6, 80, 24);
+        layout.msg_set_pos(6, 20);
+        assert_eq!(layout.grid_at(21, 10), (6, 1, 10));
+        assert_eq!(layout.grid_at(19, 10), (2, 19, 10));
+    }
+}
@@ -2,43 +2,106 @@ extern crate sdl2;
 
 use std::collections::HashMap;
 use std::env;
-use std::path::PathBuf;
-use std::sync::mpsc::channel;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver};
+use std::sync::{Arc, Mutex};
 use std::cmp::max;
+use std::process::Command;
 use std::thread;
 use std::time::{Instant, Duration};
 
 use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::{Keycode, Mod};
-use sdl2::mouse::MouseButton;
+use sdl2::mouse::{MouseButton, MouseWheelDirection};
+use sdl2::ttf::Sdl2TtfContext;
+use sdl2::video::FullscreenType;
+
+#[cfg(unix)]
+use signal_hook::consts::SIGHUP;
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+mod api;
+
+mod coalesce;
+use coalesce::{ClientSender, QUEUE_SIZE};
+
+mod cluster;
+
+mod decoration;
+
+mod config;
+use config::Config;
+
+mod grid;
+use grid::Grids;
+
+mod health;
+use health::HealthInfo;
+
+mod font;
+use font::{FontFace, FontStyle, Fonts, GuiFont};
+
+mod gesture;
+use gesture::{mousescroll_lines, Gestures};
+
+mod keyboard;
+use keyboard::Keyboard;
+
+mod layout;
+use layout::{Layout, Region, DEFAULT_GRID};
+
+mod mouse;
+use mouse::{Hover, Pointer, Wheel};
 
 mod pane;
-use pane::{Pane, TextCell};
+use pane::Pane;
+
+mod shaping;
+
+mod shortcuts;
+use shortcuts::Action;
+
+mod system_fonts;
 
 mod neovim_connector;
-use neovim_connector::{ClientEvent, NvimEvent, NvimMode};
+use neovim_connector::{ClientEvent, Failure, Highlight, NvimEvent, NvimMode};
 
-type Grid = Vec<Vec<TextCell>>;
+/// A second request to quit within this time quits without waiting for nvim.
+const FORCE_QUIT_TIMEOUT: Duration = Duration::from_secs(3);
 
-fn new_grid(cols: usize, rows: usize) -> Grid {
-    vec![vec![TextCell::new(); cols]; rows]
-}
+/// The mouse id SDL uses for mouse events synthesized from touches.
+const TOUCH_MOUSE_ID: u32 = u32::MAX;
 
 #[derive(Copy, Clone)]
 enum MouseButtonState {
     Left,
     Right,
     Middle,
+    X1,
+    X2,
     Nil,
 }
 
 impl MouseButtonState {
+    fn from_button(button: MouseButton) -> Self {
+        match button {
+            MouseButton::Left => Self::Left,
+            MouseButton::Right => Self::Right,
+            MouseButton::Middle => Self::Middle,
+            MouseButton::X1 => Self::X1,
+            MouseButton::X2 => Self::X2,
+            _ => Self::Nil,
+        }
+    }
+
     fn to_string(self) -> String {
         match self {
             Self::Nil => "".into(),
             Self::Left => "left".into(),
             Self::Right => "right".into(),
             Self::Middle => "middle".into(),
+            Self::X1 => "x1".into(),
+            Self::X2 => "x2".into(),
         }
     }
 }
@@ -47,49 +110,199 @@ struct InputState {
     alt_down: bool,
     ctrl_down: bool,
     shift_down: bool,
+    gui_down: bool,
     mouse_row: i32,
     mouse_col: i32,
+    /// The grid the held mouse button was pressed on.
+    mouse_grid: i64,
     mouse_button: MouseButtonState,
     mode: NvimMode,
     num_rows: i64,
     num_cols: i64,
 }
 
-fn select_font() -> Option<PathBuf> {
-    match font_kit::source::SystemSource::new().select_best_match(
-        &[font_kit::family_name::FamilyName::Monospace],
-        &font_kit::properties::Properties::new(),
-    ) {
-        Ok(font_kit::handle::Handle::Path { path, .. }) => Some(path),
-        _ => None,
-    }
-}
-
 fn update_modifier_state(keymod: &Mod, state: &mut InputState) {
     state.shift_down = keymod.contains(Mod::LSHIFTMOD) || keymod.contains(Mod::RSHIFTMOD);
     state.ctrl_down = keymod.contains(Mod::LCTRLMOD) || keymod.contains(Mod::RCTRLMOD);
     state.alt_down = keymod.contains(Mod::LALTMOD) || keymod.contains(Mod::RALTMOD);
+    state.gui_down = keymod.contains(Mod::LGUIMOD) || keymod.contains(Mod::RGUIMOD);
 }
 
-fn main() {
+/// Returns the held modifiers in the format `nvim_input_mouse` expects, e.g. "D-C-".
+fn mouse_modifier(state: &InputState) -> String {
+    format!(
+        "{}{}{}{}",
+        if state.gui_down { "D-" } else { "" },
+        if state.alt_down { "M-" } else { "" },
+        if state.ctrl_down { "C-" } else { "" },
+        if state.shift_down { "S-" } else { "" },
+    )
+}
+
+/// Asks nvim for as many rows and columns as fit in a window of the given size.
+fn request_grid_size(w: u32, h: u32, pane: &Pane, sender: &ClientSender) {
+    sender
+        .send(ClientEvent::WindowResize {
+            cols: (w / pane.col_width) as i64,
+            rows: (h / pane.row_height) as i64,
+        })
+        .unwrap();
+}
+
+/// Sends a mouse event for the cell under the pointer. Presses and wheel events go to the grid
+/// under the pointer, drags and releases to the grid the button was pressed on.
+fn send_mouse(
+    button: &str,
+    action: &str,
+    state: &mut InputState,
+    layout: &Layout,
+    sender: &ClientSender,
+) {
+    let (row, col) = (state.mouse_row.into(), state.mouse_col.into());
+    let (grid, row, col) = match action {
+        "drag" | "release" => layout.relative_to(state.mouse_grid, row, col),
+        _ => layout.grid_at(row, col),
+    };
+    if action == "press" {
+        state.mouse_grid = grid;
+    }
+    sender
+        .send(ClientEvent::Mouse {
+            button: button.into(),
+            action: action.into(),
+            modifier: mouse_modifier(state),
+            grid,
+            row,
+            col,
+            count: 1,
+        })
+        .unwrap();
+}
+
+/// Sends whole `(horizontal, vertical)` wheel steps, positive for right and up.
+fn send_scroll((x, y): (i32, i32), state: &mut InputState, layout: &Layout, sender: &ClientSender) {
+    let x_action = if x > 0 { "right" } else { "left" };
+    for _ in 0..x.abs() {
+        send_mouse("wheel", x_action, state, layout, sender);
+    }
+    let y_action = if y > 0 { "up" } else { "down" };
+    for _ in 0..y.abs() {
+        send_mouse("wheel", y_action, state, layout, sender);
+    }
+}
+
+/// Whether a touch device is a touchscreen, as opposed to a touchpad that already scrolls
+/// through wheel events.
+fn is_touchscreen(touch_id: i64) -> bool {
+    let device_type = unsafe { sdl2::sys::SDL_GetTouchDeviceType(touch_id) };
+    device_type == sdl2::sys::SDL_TouchDeviceType::SDL_TOUCH_DEVICE_DIRECT
+}
+
+/// The faces to draw with, in the order they're tried for each character.
+fn font_chain(primary: &FontFace, wide: &[FontFace], fallback: &[FontFace]) -> Vec<FontFace> {
+    let mut faces = vec![primary.clone()];
+    faces.extend(wide.iter().chain(fallback).cloned());
+    faces
+}
+
+/// Loads fonts into the pane, which keeps its fonts if that fails. The grid keeps its size
+/// until `request_grid_size`.
+fn set_fonts<'a>(
+    ttf_context: &'a Sdl2TtfContext,
+    faces: &[FontFace],
+    wide: &[FontFace],
+    styles: &[(FontStyle, FontFace)],
+    size: u16,
+    pane: &mut Pane<'a>,
+) -> Result<(), String> {
+    let fonts = Fonts::new(ttf_context, faces, wide, styles, size)?;
+    pane.set_fonts(fonts);
+    Ok(())
+}
+
+/// Reloads the pane's fonts at another size. The grid keeps its size until `request_grid_size`.
+fn set_font_size(size: u16, pane: &mut Pane) {
+    if let Err(e) = pane.set_font_size(size) {
+        println!("Failed to load font: {}", e);
+    }
+}
+
+/// Starts nvim on a new connector thread and returns the channels to talk to it.
+fn start_nvim(
+    queue_depth: &Arc<AtomicUsize>,
+    health: &Arc<Mutex<HealthInfo>>,
+) -> (Receiver<NvimEvent>, ClientSender) {
     let (server_sender, server_receiver) = channel();
-    let (client_sender, client_receiver) = channel();
+    let (client_sender, client_receiver) = sync_channel(QUEUE_SIZE);
+    let queue_depth = queue_depth.clone();
+    let health = health.clone();
     thread::spawn(move || {
-        neovim_connector::start(server_sender, client_receiver, env::args());
+        neovim_connector::start(
+            server_sender,
+            client_receiver,
+            env::args(),
+            queue_depth,
+            health,
+        );
     });
+    (server_receiver, ClientSender::new(client_sender))
+}
+
+/// Records the font for :checkhealth.
+fn font_health(health: &Mutex<HealthInfo>, face: &FontFace, size: u16, pane: &Pane) {
+    let mut health = health.lock().unwrap();
+    health.font_path = Some(face.path.clone());
+    health.font_size = size;
+    health.cell_size = (pane.col_width, pane.row_height);
+}
+
+/// The error screen shown after nvim failed.
+fn failure_message(failure: &Failure) -> Vec<String> {
+    let mut lines = vec![failure.reason.clone(), String::new()];
+    if !failure.stderr.is_empty() {
+        lines.push("Last output:".into());
+        lines.extend(failure.stderr.iter().map(|line| format!("    {}", line)));
+        lines.push(String::new());
+    }
+    lines.push("Press r to restart nvim or q to quit.".into());
+    lines
+}
+
+fn main() {
+    let config = Config::load();
+
+    let queue_depth = Arc::new(AtomicUsize::new(0));
+    let health = Arc::new(Mutex::new(HealthInfo::default()));
+    let (mut server_receiver, mut client_sender) = start_nvim(&queue_depth, &health);
 
     let mut state = InputState {
         alt_down: false,
         ctrl_down: false,
         shift_down: false,
+        gui_down: false,
         mouse_row: 0,
         mouse_col: 0,
+        mouse_grid: 0,
         mouse_button: MouseButtonState::Nil,
         mode: NvimMode::Normal,
         num_rows: 0,
         num_cols: 0,
     };
 
+    // Signals ask nvim to quit like closing the window does, instead of SDL's handlers turning
+    // only some of them into quit events.
+    sdl2::hint::set("SDL_NO_SIGNAL_HANDLERS", "1");
+    let quit_signal = Arc::new(AtomicBool::new(false));
+    let mut signals = vec![SIGINT, SIGTERM];
+    // Closing the terminal nvim-sdl was started from, which only unix has.
+    #[cfg(unix)]
+    signals.push(SIGHUP);
+    for &signal in &signals {
+        if let Err(e) = signal_hook::flag::register(signal, quit_signal.clone()) {
+            println!("Failed to handle signal {}: {}", signal, e);
+        }
+    }
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsys = sdl_context.video().unwrap();
     let ttf_context = sdl2::ttf::init().unwrap();
@@ -103,317 +316,373 @@ fn main() {
         .unwrap();
     // let mut canvas = window.into_canvas().present_vsync().build().unwrap();
     let mut canvas = window.into_canvas().build().unwrap();
+    if config.opacity < 1.0 {
+        if let Err(e) = canvas.window_mut().set_opacity(config.opacity) {
+            println!("Failed to set the window opacity: {}", e);
+        }
+    }
+
+    let mut font_face = GuiFont::default().resolve().unwrap();
+    let mut style_faces = GuiFont::default().resolve_styles();
+    // The size zoom_reset goes back to, from 'guifont' or the config.
+    let mut base_font_size = config.font_size;
+    let mut font_size = base_font_size;
+    // Fonts tried after 'guifont' and 'guifontwide' for characters those don't have.
+    let fallback_faces = match GuiFont::parse(&config.font_fallback) {
+        Ok(font) => {
+            let (faces, result) = font.resolve_each();
+            if let Err(e) = result {
+                println!("font_fallback: {}", e);
+            }
+            faces
+        }
+        Err(e) => {
+            println!("font_fallback: {}", e);
+            Vec::new()
+        }
+    };
+    let mut wide_faces = Vec::new();
+    let fonts = Fonts::new(
+        &ttf_context,
+        &font_chain(&font_face, &wide_faces, &fallback_faces),
+        &wide_faces,
+        &style_faces,
+        font_size,
+    )
+    .unwrap();
+    fonts.index_system_fonts();
+    let mut pane = Pane::new(fonts);
+    pane.enabled_styles = FontStyle {
+        bold: config.bold,
+        italic: config.italic,
+    };
+    pane.font_features = config.font_features.clone();
+    font_health(&health, &font_face, font_size, &pane);
+    {
+        let mut health = health.lock().unwrap();
+        health.renderer = canvas.info().name.into();
+        health.video_driver = video_subsys.current_video_driver().into();
+        let (output_w, _) = canvas.output_size().unwrap();
+        health.scale = output_w as f32 / canvas.window().size().0 as f32;
+        health.dpi = video_subsys.display_dpi(0).ok().map(|(ddpi, _, _)| ddpi);
+    }
 
-    let font = ttf_context.load_font(&select_font().unwrap(), 16).unwrap();
-    let col_width = font.size_of_char('W').unwrap().0 as i32;
-    let row_height = font.height();
     let (window_w, window_h) = canvas.window().size();
-    state.num_cols = window_w as i64 / col_width as i64;
-    state.num_rows = window_h as i64 / row_height as i64;
-    client_sender
-        .send(ClientEvent::WindowResize {
-            cols: state.num_cols,
-            rows: state.num_rows,
-        })
-        .unwrap();
+    state.num_cols = (window_w / pane.col_width) as i64;
+    state.num_rows = (window_h / pane.row_height) as i64;
+    request_grid_size(window_w, window_h, &pane, &client_sender);
+
+    let mut grids = Grids::default();
+    grids.resize(DEFAULT_GRID, state.num_cols, state.num_rows);
+    // The grid the cursor is on, and its position in that grid.
+    let mut cursor = (DEFAULT_GRID, 0, 0);
 
-    let mut text = new_grid(state.num_cols as usize, state.num_rows as usize);
+    let mut highlight_table: HashMap<i64, Highlight> = HashMap::new();
 
-    let mut pane = Pane::new(font);
+    let mut layout = Layout::default();
+    let mut hover = Hover::default();
+    let mut wheel = Wheel::default();
+    let mut gestures = Gestures::default();
+    let mut pointer = Pointer::new(sdl_context.mouse());
 
-    let mut highlight_table = HashMap::new();
+    let mut keyboard = Keyboard::new();
+    let mut actions = Vec::new();
+    let mut debug_overlay = false;
+    let mut quit_requested_at: Option<Instant> = None;
+    let mut failure: Option<Failure> = None;
 
     let mut time = Instant::now();
 
     'mainloop: loop {
-        // println!("{:?}", time.elapsed());
-        // time = Instant::now();
+        let frame_time = time.elapsed();
+        time = Instant::now();
         let mut dirty = false;
+        let mut restart = false;
+        // Pinching changes the font size by this many points, applied once for the frame as
+        // reloading the fonts reads every font file again.
+        let mut pinch_points = 0;
         for event in sdl_context.event_pump().unwrap().poll_iter() {
+            if failure.is_some() {
+                match event {
+                    Event::KeyDown {
+                        keycode: Some(Keycode::R),
+                        ..
+                    } => restart = true,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Q),
+                        ..
+                    }
+                    | Event::Quit { .. } => break 'mainloop,
+                    Event::Window { .. } => dirty = true,
+                    _ => {}
+                }
+                continue;
+            }
             match event {
-                Event::Quit { .. } => break 'mainloop,
+                Event::Quit { .. } => actions.push(Action::Quit),
                 Event::KeyDown {
-                    keycode: Some(kc),
+                    keycode,
+                    scancode,
                     keymod,
                     ..
                 } => {
                     update_modifier_state(&keymod, &mut state);
-                    // These keys should only be send with a modifier, otherwise they're handled by
-                    // the text input event.
-                    let mut key_to_send = match kc {
-                        Keycode::Space => "Space",
-                        Keycode::Comma => ",",
-                        Keycode::Minus => "-",
-                        Keycode::Period => ".",
-                        Keycode::Slash => "/",
-                        Keycode::Num0 => "0",
-                        Keycode::Num1 => "1",
-                        Keycode::Num2 => "2",
-                        Keycode::Num3 => "3",
-                        Keycode::Num4 => "4",
-                        Keycode::Num5 => "5",
-                        Keycode::Num6 => "6",
-                        Keycode::Num7 => "7",
-                        Keycode::Num8 => "8",
-                        Keycode::Num9 => "9",
-                        Keycode::Colon => "",
-                        Keycode::Semicolon => ";",
-                        Keycode::Equals => "=",
-                        Keycode::LeftBracket => "[",
-                        Keycode::Backslash => "\\",
-                        Keycode::RightBracket => "]",
-                        Keycode::Backquote => "`",
-                        Keycode::A => "a",
-                        Keycode::B => "b",
-                        Keycode::C => "c",
-                        Keycode::D => "d",
-                        Keycode::E => "e",
-                        Keycode::F => "f",
-                        Keycode::G => "g",
-                        Keycode::H => "h",
-                        Keycode::I => "i",
-                        Keycode::J => "j",
-                        Keycode::K => "k",
-                        Keycode::L => "l",
-                        Keycode::M => "m",
-                        Keycode::N => "n",
-                        Keycode::O => "o",
-                        Keycode::P => "p",
-                        Keycode::Q => "q",
-                        Keycode::R => "r",
-                        Keycode::S => "s",
-                        Keycode::T => "t",
-                        Keycode::U => "u",
-                        Keycode::V => "v",
-                        Keycode::W => "w",
-                        Keycode::X => "x",
-                        Keycode::Y => "y",
-                        Keycode::Z => "z",
-                        Keycode::Caret => "^",
-                        _ => "",
-                    };
-                    if key_to_send != "" && (state.ctrl_down || state.alt_down) {
-                        if state.shift_down {
-                            key_to_send = match key_to_send {
-                                "0" => ")",
-                                "1" => "!",
-                                "2" => "@",
-                                "3" => "#",
-                                "4" => "$",
-                                "5" => "%",
-                                "6" => "^",
-                                "7" => "&",
-                                "8" => "*",
-                                "9" => "(",
-                                "," => "<",
-                                "-" => "_",
-                                "." => ">",
-                                "/" => "?",
-                                ";" => ":",
-                                "=" => "+",
-                                "[" => "{",
-                                "\\" => "|",
-                                "]" => "}",
-                                "`" => "~",
-                                _ => key_to_send,
-                            };
+                    let keys = keyboard
+                        .flush()
+                        .into_iter()
+                        .chain(keyboard.key_down(keycode, scancode, keymod));
+                    for key in keys {
+                        match config.shortcuts.lookup(&key) {
+                            Some(action) => actions.push(action),
+                            None => {
+                                client_sender.send(ClientEvent::Text(key)).unwrap();
+                                pointer.typed();
+                            }
                         }
-                        client_sender
-                            .send(ClientEvent::Text(format!(
-                                "<{}{}{}>",
-                                if state.alt_down { "M-" } else { "" },
-                                if state.ctrl_down { "C-" } else { "" },
-                                key_to_send,
-                            )))
-                            .unwrap();
-                    }
-
-                    // These keys should always be sent, regardless of modifiers.
-                    let key_to_send = match kc {
-                        Keycode::Backspace => "BS",
-                        Keycode::Tab => "Tab",
-                        Keycode::Return => "CR",
-                        Keycode::Escape => "Esc",
-                        Keycode::Delete => "Del",
-                        Keycode::CapsLock => "",
-                        Keycode::F1 => "F1",
-                        Keycode::F2 => "F2",
-                        Keycode::F3 => "F3",
-                        Keycode::F4 => "F4",
-                        Keycode::F5 => "F5",
-                        Keycode::F6 => "F6",
-                        Keycode::F7 => "F7",
-                        Keycode::F8 => "F8",
-                        Keycode::F9 => "F9",
-                        Keycode::F10 => "F10",
-                        Keycode::F11 => "F11",
-                        Keycode::F12 => "F12",
-                        Keycode::PrintScreen => "",
-                        Keycode::ScrollLock => "",
-                        Keycode::Pause => "",
-                        Keycode::Insert => "Insert",
-                        Keycode::Home => "Home",
-                        Keycode::PageUp => "PageUp",
-                        Keycode::End => "End",
-                        Keycode::PageDown => "PageDown",
-                        Keycode::Right => "Right",
-                        Keycode::Left => "Left",
-                        Keycode::Down => "Down",
-                        Keycode::Up => "Up",
-                        Keycode::NumLockClear => "",
-                        Keycode::KpDivide => "kDivide",
-                        Keycode::KpMultiply => "kMultiply",
-                        Keycode::KpMinus => "kMinus",
-                        Keycode::KpPlus => "kPlus",
-                        Keycode::KpEnter => "kEnter",
-                        Keycode::Kp1 => "k1",
-                        Keycode::Kp2 => "k2",
-                        Keycode::Kp3 => "k3",
-                        Keycode::Kp4 => "k4",
-                        Keycode::Kp5 => "k5",
-                        Keycode::Kp6 => "k6",
-                        Keycode::Kp7 => "k7",
-                        Keycode::Kp8 => "k8",
-                        Keycode::Kp9 => "k9",
-                        Keycode::Kp0 => "k0",
-                        Keycode::KpPeriod => "kPoint",
-                        Keycode::Execute => "",
-                        Keycode::Help => "Help",
-                        Keycode::Undo => "Undo",
-                        _ => "",
-                    };
-
-                    if key_to_send != "" {
-                        client_sender
-                            .send(ClientEvent::Text(format!(
-                                "<{}{}{}{}>",
-                                if state.alt_down { "M-" } else { "" },
-                                if state.ctrl_down { "C-" } else { "" },
-                                if state.shift_down { "S-" } else { "" },
-                                key_to_send,
-                            )))
-                            .unwrap();
                     }
                 }
                 Event::TextInput { text, .. } => {
-                    client_sender.send(ClientEvent::Text(text.replace("<", "<lt>"))).unwrap();
+                    if let Some(text) = keyboard.text_input(&text) {
+                        client_sender.send(ClientEvent::Text(text)).unwrap();
+                        pointer.typed();
+                    }
+                }
+                Event::TextEditing { text, .. } => {
+                    keyboard.text_editing(&text);
                 }
                 Event::KeyUp { keymod, .. } => {
                     update_modifier_state(&keymod, &mut state);
                 }
-                Event::Window { win_event, .. } => {
-                    if let WindowEvent::Resized(w, h) = win_event {
-                        let num_cols = w as i64 / col_width as i64;
-                        let num_rows = h as i64 / row_height as i64;
-                        client_sender
-                            .send(ClientEvent::WindowResize {
-                                cols: num_cols,
-                                rows: num_rows,
-                            })
-                            .unwrap();
+                Event::Window { win_event, .. } => match win_event {
+                    WindowEvent::Resized(w, h) => {
+                        request_grid_size(w as u32, h as u32, &pane, &client_sender);
                     }
-                }
-                Event::MouseButtonDown {
-                    x,
-                    y,
-                    clicks,
-                    mouse_btn,
-                    ..
-                } => {
-                    state.mouse_col = x / col_width;
-                    state.mouse_row = y / row_height;
-                    let button = match mouse_btn {
-                        MouseButton::Left => MouseButtonState::Left,
-                        MouseButton::Right => MouseButtonState::Right,
-                        MouseButton::Middle => MouseButtonState::Middle,
-                        _ => MouseButtonState::Nil,
-                    };
+                    WindowEvent::FocusGained => {
+                        client_sender.send(ClientEvent::Focus(true)).unwrap();
+                    }
+                    WindowEvent::FocusLost => {
+                        client_sender.send(ClientEvent::Focus(false)).unwrap();
+                    }
+                    _ => {}
+                },
+                Event::MouseButtonDown { x, y, mouse_btn, .. } => {
+                    state.mouse_col = x / pane.col_width as i32;
+                    state.mouse_row = y / pane.row_height as i32;
+                    let button = MouseButtonState::from_button(mouse_btn);
                     state.mouse_button = button;
+                    pointer.show();
                     match button {
                         MouseButtonState::Nil => {}
-                        _ => {
-                            for _ in 0..clicks {
-                                client_sender
-                                    .send(ClientEvent::Mouse {
-                                        button: button.to_string(),
-                                        action: "press".into(),
-                                        modifier: "".into(), // TODO
-                                        grid: 0,
-                                        col: state.mouse_col.into(),
-                                        row: state.mouse_row.into(),
-                                    })
-                                    .unwrap();
-                            }
-                        }
+                        // nvim counts double and triple clicks itself, from presses that follow
+                        // each other within 'mousetime'.
+                        _ => send_mouse(
+                            &button.to_string(),
+                            "press",
+                            &mut state,
+                            &layout,
+                            &client_sender,
+                        ),
                     }
                 }
                 Event::MouseButtonUp { mouse_btn, .. } => {
-                    let button = match mouse_btn {
-                        MouseButton::Left => "left",
-                        MouseButton::Right => "right",
-                        MouseButton::Middle => "middle",
-                        _ => "",
-                    };
-                    if button != "" {
-                        client_sender
-                            .send(ClientEvent::Mouse {
-                                button: button.into(),
-                                action: "release".into(),
-                                modifier: "".into(), // TODO
-                                grid: 0,
-                                col: state.mouse_col.into(),
-                                row: state.mouse_row.into(),
-                            })
-                        .unwrap();
+                    let button = MouseButtonState::from_button(mouse_btn);
+                    match button {
+                        MouseButtonState::Nil => {}
+                        _ => send_mouse(
+                            &button.to_string(),
+                            "release",
+                            &mut state,
+                            &layout,
+                            &client_sender,
+                        ),
                     }
                     state.mouse_button = MouseButtonState::Nil;
                 }
+                // SDL also turns the first finger of a touch into a mouse, which would drag while
+                // two fingers scroll.
+                Event::MouseMotion { which, .. }
+                    if which == TOUCH_MOUSE_ID && gestures.fingers() >= 2 => {}
                 Event::MouseMotion { x, y, .. } => {
+                    state.mouse_col = x / pane.col_width as i32;
+                    state.mouse_row = y / pane.row_height as i32;
                     match state.mouse_button {
-                        MouseButtonState::Nil => {}
+                        MouseButtonState::Nil => {
+                            let (row, col) = (state.mouse_row, state.mouse_col);
+                            hover.motion(row, col);
+                            let cell = grids
+                                .get(DEFAULT_GRID)
+                                .and_then(|grid| grid.get(row as usize)?.get(col as usize))
+                                .and_then(|cell| highlight_table.get(&cell.hl_id))
+                                .and_then(|hl| Region::from_ui_groups(&hl.ui_groups));
+                            pointer.moved(layout.region_at(row.into(), col.into(), cell));
+                        }
+                        // Keep the shape the drag started with, e.g. while resizing a window.
                         _ => {
-                            state.mouse_col = x / col_width;
-                            state.mouse_row = y / row_height;
-                            client_sender
-                                .send(ClientEvent::Mouse {
-                                    button: state.mouse_button.to_string(),
-                                    action: "drag".into(),
-                                    modifier: "".into(), // TODO
-                                    grid: 0,
-                                    col: state.mouse_col.into(),
-                                    row: state.mouse_row.into(),
-                                })
-                                .unwrap();
+                            pointer.show();
+                            send_mouse(
+                                &state.mouse_button.to_string(),
+                                "drag",
+                                &mut state,
+                                &layout,
+                                &client_sender,
+                            );
                         }
                     }
                 }
-                Event::MouseWheel { x, y, .. } => {
-                    let x_norm = x / max(1, x.abs());
-                    let y_norm = y / max(1, y.abs());
-                    let action = match (x_norm, y_norm) {
-                        (0, 1) => "up",
-                        (0, -1) => "down",
-                        (1, 0) => "right",
-                        (-1, 0) => "left",
-                        _ => "",
+                Event::MouseWheel {
+                    direction,
+                    precise_x,
+                    precise_y,
+                    ..
+                } => {
+                    pointer.show();
+                    let sign = match direction {
+                        MouseWheelDirection::Flipped => -1.0,
+                        _ => 1.0,
                     };
-                    if action != "" {
-                        for _ in 0..y.abs() {
-                            client_sender
-                                .send(ClientEvent::Mouse {
-                                    button: "wheel".into(),
-                                    action: action.into(),
-                                    modifier: "".into(), // TODO
-                                    grid: 0,
-                                    col: state.mouse_col.into(),
-                                    row: state.mouse_row.into(),
-                                }).unwrap();
-                        }
+                    let steps = wheel.scroll(
+                        sign * precise_x,
+                        sign * precise_y,
+                        config.lines_per_notch,
+                    );
+                    send_scroll(steps, &mut state, &layout, &client_sender);
+                }
+                Event::FingerDown { finger_id, .. } => {
+                    gestures.finger_down(finger_id);
+                }
+                Event::FingerUp { finger_id, .. } => {
+                    gestures.finger_up(finger_id, Instant::now(), config.kinetic_scroll);
+                }
+                Event::MultiGesture {
+                    touch_id,
+                    d_dist,
+                    y,
+                    num_fingers,
+                    ..
+                } => {
+                    let (points, scroll) = gestures.multi_gesture(
+                        d_dist,
+                        y,
+                        num_fingers,
+                        state.num_rows as f32,
+                        Instant::now(),
+                    );
+                    pinch_points += points;
+                    if is_touchscreen(touch_id) {
+                        let steps = wheel.scroll(0.0, scroll, 1.0);
+                        send_scroll(steps, &mut state, &layout, &client_sender);
                     }
                 }
                 _ => {}
             }
         }
+        if pinch_points != 0 {
+            let size = max(4, font_size as i32 + pinch_points) as u16;
+            if size != font_size {
+                font_size = size;
+                set_font_size(font_size, &mut pane);
+                font_health(&health, &font_face, font_size, &pane);
+                dirty = true;
+            }
+        }
+        if restart {
+            let (receiver, sender) = start_nvim(&queue_depth, &health);
+            server_receiver = receiver;
+            client_sender = sender;
+            failure = None;
+            grids = Grids::default();
+            grids.resize(DEFAULT_GRID, state.num_cols, state.num_rows);
+            highlight_table.clear();
+            layout = Layout::default();
+            let (w, h) = canvas.window().size();
+            request_grid_size(w, h, &pane, &client_sender);
+            dirty = true;
+        }
+
+        client_sender.flush().unwrap();
+        let (scroll, pinch_settled) = gestures.tick(Instant::now());
+        if scroll != 0.0 {
+            let steps = wheel.scroll(0.0, scroll, 1.0);
+            send_scroll(steps, &mut state, &layout, &client_sender);
+        }
+        if pinch_settled {
+            let (w, h) = canvas.window().size();
+            request_grid_size(w, h, &pane, &client_sender);
+        }
+        if hover.poll(Instant::now()).is_some() {
+            // "move" takes no action, it only reports the hovered cell.
+            send_mouse("move", "", &mut state, &layout, &client_sender);
+        }
+        if let Some(key) = keyboard.flush() {
+            match config.shortcuts.lookup(&key) {
+                Some(action) => actions.push(action),
+                None => {
+                    client_sender.send(ClientEvent::Text(key)).unwrap();
+                    pointer.typed();
+                }
+            }
+        }
+
+        if quit_signal.swap(false, Ordering::Relaxed) {
+            actions.push(Action::Quit);
+        }
+
+        for action in actions.drain(..) {
+            match action {
+                Action::ZoomIn | Action::ZoomOut | Action::ZoomReset => {
+                    font_size = match action {
+                        Action::ZoomIn => font_size + 1,
+                        Action::ZoomOut => max(4, font_size.saturating_sub(1)),
+                        _ => base_font_size,
+                    };
+                    set_font_size(font_size, &mut pane);
+                    font_health(&health, &font_face, font_size, &pane);
+                    let (w, h) = canvas.window().size();
+                    request_grid_size(w, h, &pane, &client_sender);
+                    dirty = true;
+                }
+                Action::ToggleFullscreen => {
+                    let window = canvas.window_mut();
+                    let fullscreen = match window.fullscreen_state() {
+                        FullscreenType::Off => FullscreenType::Desktop,
+                        _ => FullscreenType::Off,
+                    };
+                    if let Err(e) = window.set_fullscreen(fullscreen) {
+                        println!("Failed to toggle fullscreen: {}", e);
+                    }
+                }
+                Action::Copy => {
+                    if state.mode == NvimMode::Visual {
+                        client_sender.send(ClientEvent::Copy).unwrap();
+                    }
+                }
+                Action::Paste => match video_subsys.clipboard().clipboard_text() {
+                    Ok(text) => client_sender.send(ClientEvent::Paste(text)).unwrap(),
+                    Err(e) => println!("Failed to read clipboard: {}", e),
+                },
+                Action::NewWindow => {
+                    if let Err(e) = env::current_exe().and_then(|exe| Command::new(exe).spawn()) {
+                        println!("Failed to open a new window: {}", e);
+                    }
+                }
+                Action::DebugOverlay => {
+                    debug_overlay = !debug_overlay;
+                    dirty = true;
+                }
+                Action::Quit => {
+                    let forced =
+                        quit_requested_at.is_some_and(|t| t.elapsed() < FORCE_QUIT_TIMEOUT);
+                    // Without nvim there is nothing to confirm.
+                    if forced || failure.is_some() {
+                        break 'mainloop;
+                    }
+                    quit_requested_at = Some(Instant::now());
+                    client_sender
+                        .send(ClientEvent::Command(config.quit_command.clone()))
+                        .unwrap();
+                }
+            }
+        }
 
         'notifyloop: loop {
             match server_receiver.try_recv() {
@@ -421,66 +690,22 @@ fn main() {
                     match event {
                     NvimEvent::GridLine(entries) => {
                         // dirty = true;
-                        let mut last_hl_id = -1;
-                        for entry in entries {
-                            let row = entry.row as usize;
-                            let mut col = entry.col as usize;
-                            for cell in entry.cells {
-                                let hl = if cell.highlight == -1 {
-                                    last_hl_id
-                                } else {
-                                    cell.highlight
-                                };
-                                for _ in 0..cell.repeat {
-                                    text[row][col] = TextCell {
-                                        text: cell.text.clone(),
-                                        hl_id: hl,
-                                    };
-                                    col += 1;
-                                }
-                                last_hl_id = if cell.highlight == -1 {
-                                    last_hl_id
-                                } else {
-                                    cell.highlight
-                                };
-                            }
-                        }
+                        grids.line(entries);
                     }
                     NvimEvent::Flush => {
                         dirty = true;
                     }
-                    NvimEvent::GridCursorGoto(_grid, row, col) => {
+                    NvimEvent::GridCursorGoto(grid, row, col) => {
                         // dirty = true;
-                        pane.cursor_row = row as i32;
-                        pane.cursor_col = col as i32;
+                        cursor = (grid, row, col);
                     }
-                    NvimEvent::GridClear(_) => {
+                    NvimEvent::GridClear(grid) => {
                         // dirty = true;
-                        text = new_grid(state.num_cols as usize, state.num_rows as usize);
+                        grids.clear(grid);
                     }
                     NvimEvent::GridScroll(e) => {
                         // dirty = true;
-                        if e.rows > 0 {
-                            for y in e.top..e.bot {
-                                for x in e.left..e.right {
-                                    if y - e.rows >= e.top {
-                                        text[(y - e.rows) as usize][x as usize] =
-                                            text[y as usize][x as usize].clone();
-                                        text[y as usize][x as usize] = TextCell::new();
-                                    }
-                                }
-                            }
-                        } else {
-                            for y in (e.top..e.bot).rev() {
-                                for x in (e.left..e.right).rev() {
-                                    if y - e.rows < e.bot {
-                                        text[(y - e.rows) as usize][x as usize] =
-                                            text[y as usize][x as usize].clone();
-                                        text[y as usize][x as usize] = TextCell::new();
-                                    }
-                                }
-                            }
-                        }
+                        grids.scroll(&e);
                     }
                     NvimEvent::DefaultColorsSet { fg, bg, special } => {
                         pane.set_colors(fg, bg, special);
@@ -488,6 +713,16 @@ fn main() {
                     NvimEvent::Close => {
                         break 'mainloop;
                     }
+                    NvimEvent::Copied(text) => {
+                        // The same clipboard Paste reads, whether nvim has a provider or not.
+                        if let Err(e) = video_subsys.clipboard().set_clipboard_text(&text) {
+                            println!("Failed to write clipboard: {}", e);
+                        }
+                    }
+                    NvimEvent::Failed(f) => {
+                        failure = Some(f);
+                        dirty = true;
+                    }
                     NvimEvent::ModeChange(mode) => {
                         state.mode = mode;
                     }
@@ -495,13 +730,117 @@ fn main() {
                     NvimEvent::HighlightAttrDefine { id, hl } => {
                         highlight_table.insert(id, hl);
                     }
-                    NvimEvent::GridResize { cols, rows, .. } => {
-                        state.num_cols = cols;
-                        state.num_rows = rows;
-                        text = new_grid(state.num_cols as usize, state.num_rows as usize);
-                        let (w, h) = canvas.window().size();
-                        pane.w = w as u32;
-                        pane.h = h as u32;
+                    NvimEvent::GridResize { grid, cols, rows } => {
+                        layout.grid_resize(grid, cols, rows);
+                        grids.resize(grid, cols, rows);
+                        if grid == DEFAULT_GRID {
+                            state.num_cols = cols;
+                            state.num_rows = rows;
+                            let (w, h) = canvas.window().size();
+                            pane.w = w as u32;
+                            pane.h = h as u32;
+                        }
+                    }
+                    NvimEvent::GridDestroy(grid) => {
+                        layout.grid_destroy(grid);
+                        grids.destroy(grid);
+                    }
+                    NvimEvent::WinPos { grid, row, col, width, height } => {
+                        layout.win_pos(grid, row, col, width, height);
+                    }
+                    NvimEvent::WinFloatPos(float) => {
+                        layout.win_float_pos(&float);
+                    }
+                    NvimEvent::WinHide(grid) => {
+                        layout.win_hide(grid);
+                    }
+                    NvimEvent::MsgSetPos { grid, row } => {
+                        layout.msg_set_pos(grid, row);
+                    }
+                    NvimEvent::OptionSet { name, value } => {
+                        match name.as_str() {
+                            "mousemoveevent" => hover.set_enabled(value.as_bool()),
+                            "mousehide" => pointer.set_hide_while_typing(value.as_bool()),
+                            "mousescroll" => {
+                                if let Some(lines) = value.as_str().and_then(mousescroll_lines) {
+                                    gestures.set_lines_per_wheel_event(lines);
+                                }
+                            }
+                            "guifont" => {
+                                let font = GuiFont::parse(value.as_str().unwrap_or(""))
+                                    .and_then(|font| {
+                                        Ok((font.resolve()?, font.resolve_styles(), font.size))
+                                    });
+                                let result = match font {
+                                    Ok((face, styles, size)) => {
+                                        let size =
+                                            size.map_or(config.font_size, |s| s.round() as u16);
+                                        let changed = face != font_face
+                                            || styles != style_faces
+                                            || size != font_size;
+                                        let loaded = if changed {
+                                            set_fonts(
+                                                &ttf_context,
+                                                &font_chain(&face, &wide_faces, &fallback_faces),
+                                                &wide_faces,
+                                                &styles,
+                                                size,
+                                                &mut pane,
+                                            )
+                                        } else {
+                                            Ok(())
+                                        };
+                                        if loaded.is_ok() {
+                                            base_font_size = size;
+                                        }
+                                        // :checkhealth and the overlay show what was loaded.
+                                        if loaded.is_ok() && changed {
+                                            font_face = face;
+                                            style_faces = styles;
+                                            font_size = size;
+                                            font_health(&health, &font_face, font_size, &pane);
+                                            let (w, h) = canvas.window().size();
+                                            request_grid_size(w, h, &pane, &client_sender);
+                                            dirty = true;
+                                        }
+                                        loaded
+                                    }
+                                    Err(e) => Err(e),
+                                };
+                                if let Err(e) = result {
+                                    client_sender
+                                        .send(ClientEvent::Error(format!("nvim-sdl: {}", e)))
+                                        .unwrap();
+                                }
+                            }
+                            "guifontwide" => {
+                                let font = GuiFont::parse(value.as_str().unwrap_or(""))
+                                    .map(|font| font.resolve_each());
+                                let result = match font {
+                                    Ok((faces, result)) if faces != wide_faces => set_fonts(
+                                        &ttf_context,
+                                        &font_chain(&font_face, &faces, &fallback_faces),
+                                        &faces,
+                                        &style_faces,
+                                        font_size,
+                                        &mut pane,
+                                    )
+                                    .and_then(|()| {
+                                        wide_faces = faces;
+                                        dirty = true;
+                                        result
+                                    }),
+                                    Ok((_, result)) => result,
+                                    Err(e) => Err(e),
+                                };
+                                if let Err(e) = result {
+                                    client_sender
+                                        .send(ClientEvent::Error(format!("nvim-sdl: {}", e)))
+                                        .unwrap();
+                                }
+                            }
+                            _ => {}
+                        }
                     }
                 }
                 },
@@ -512,7 +851,29 @@ fn main() {
         }
 
         if (dirty) {
-            pane.draw(&mut canvas, &text, &highlight_table);
+            if let Some(failure) = &failure {
+                pane.draw_message(&mut canvas, &failure_message(failure));
+            } else {
+                let (row, col) = layout.origin(cursor.0);
+                pane.cursor_row = (row + cursor.1) as i32;
+                pane.cursor_col = (col + cursor.2) as i32;
+                let layers = layout.layers(|grid| grids.get(grid));
+                pane.draw(&mut canvas, &layers, &highlight_table);
+            }
+            if debug_overlay {
+                let lines = [
+                    format!("frame: {:.1} ms", frame_time.as_secs_f64() * 1000.0),
+                    format!("grid: {}x{}", state.num_cols, state.num_rows),
+                    format!("cell: {}x{} px", pane.col_width, pane.row_height),
+                    format!("font: {} {}pt", font_face.path.display(), font_size),
+                    format!(
+                        "input queue: {} ({} held back)",
+                        queue_depth.load(Ordering::Relaxed),
+                        client_sender.pending()
+                    ),
+                ];
+                pane.draw_overlay(&mut canvas, &lines);
+            }
         }
         std::thread::sleep(Duration::from_millis(1));
         canvas.present();
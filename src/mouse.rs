@@ -0,0 +1,227 @@
+use std::time::{Duration, Instant};
+
+use sdl2::mouse::{Cursor, MouseUtil, SystemCursor};
+
+use crate::layout::Region;
+
+/// The shortest time between two "move" events sent to nvim.
+const MOVE_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Decides when to tell nvim that the pointer hovers over a new cell.
+///
+/// nvim only wants "move" events when `'mousemoveevent'` is set, and only the cell under the
+/// pointer matters, so motion within a cell is dropped and fast motion is throttled to the
+/// latest cell.
+#[derive(Default)]
+pub struct Hover {
+    enabled: bool,
+    last_cell: Option<(i32, i32)>,
+    pending: Option<(i32, i32)>,
+    last_sent: Option<Instant>,
+}
+
+impl Hover {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.pending = None;
+    }
+
+    /// Records that the pointer moved over `(row, col)` with no button held.
+    pub fn motion(&mut self, row: i32, col: i32) {
+        if self.enabled && self.last_cell != Some((row, col)) {
+            self.pending = Some((row, col));
+        } else {
+            self.pending = None;
+        }
+    }
+
+    /// Returns the cell to send a "move" event for, if one is due.
+    pub fn poll(&mut self, now: Instant) -> Option<(i32, i32)> {
+        let cell = self.pending?;
+        if let Some(last_sent) = self.last_sent {
+            if now.duration_since(last_sent) < MOVE_INTERVAL {
+                return None;
+            }
+        }
+        self.pending = None;
+        self.last_cell = Some(cell);
+        self.last_sent = Some(now);
+        Some(cell)
+    }
+}
+
+/// Sets the pointer's shape from what it is over, and hides it while typing like
+/// `'mousehide'`.
+pub struct Pointer {
+    mouse: MouseUtil,
+    /// The system cursors have to stay alive while they are in use.
+    cursors: Vec<(Region, Cursor)>,
+    region: Option<Region>,
+    hide_while_typing: bool,
+    hidden: bool,
+}
+
+impl Pointer {
+    pub fn new(mouse: MouseUtil) -> Self {
+        let shapes = [
+            (Region::Text, SystemCursor::IBeam),
+            (Region::StatusLine, SystemCursor::SizeNS),
+            (Region::Separator, SystemCursor::SizeWE),
+            (Region::Outside, SystemCursor::Arrow),
+        ];
+        let mut cursors = Vec::new();
+        for &(region, shape) in &shapes {
+            match Cursor::from_system(shape) {
+                Ok(cursor) => cursors.push((region, cursor)),
+                Err(e) => println!("Failed to create pointer: {}", e),
+            }
+        }
+        Self {
+            mouse,
+            cursors,
+            region: None,
+            hide_while_typing: true,
+            hidden: false,
+        }
+    }
+
+    pub fn set_hide_while_typing(&mut self, hide: bool) {
+        self.hide_while_typing = hide;
+    }
+
+    /// Shows the pointer again after typing hid it.
+    pub fn show(&mut self) {
+        if self.hidden {
+            self.mouse.show_cursor(true);
+            self.hidden = false;
+        }
+    }
+
+    /// Shows the pointer again and gives it the shape for `region`.
+    pub fn moved(&mut self, region: Region) {
+        self.show();
+        if self.region != Some(region) {
+            self.region = Some(region);
+            if let Some((_, cursor)) = self.cursors.iter().find(|(r, _)| *r == region) {
+                cursor.set();
+            }
+        }
+    }
+
+    /// Hides the pointer after a key was sent to nvim.
+    pub fn typed(&mut self) {
+        if self.hide_while_typing && !self.hidden {
+            self.mouse.show_cursor(false);
+            self.hidden = true;
+        }
+    }
+}
+
+/// Turns wheel deltas, which are fractional on touchpads and high-resolution wheels, into whole
+/// wheel events for nvim.
+#[derive(Default)]
+pub struct Wheel {
+    x: f32,
+    y: f32,
+}
+
+fn accumulate(total: &mut f32, delta: f32) -> i32 {
+    // Start over when the direction changes, so a leftover fraction doesn't delay the reversal.
+    if *total * delta < 0.0 {
+        *total = 0.0;
+    }
+    *total += delta;
+    let steps = total.trunc();
+    *total -= steps;
+    steps as i32
+}
+
+impl Wheel {
+    /// Adds a wheel delta in notches, positive for right and up, and returns the whole
+    /// `(horizontal, vertical)` steps that are ready to send.
+    pub fn scroll(&mut self, dx: f32, dy: f32, steps_per_notch: f32) -> (i32, i32) {
+        (
+            accumulate(&mut self.x, dx * steps_per_notch),
+            accumulate(&mut self.y, dy * steps_per_notch),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wheel_whole_notches() {
+        let mut wheel = Wheel::default();
+        assert_eq!(wheel.scroll(0.0, 1.0, 1.0), (0, 1));
+        assert_eq!(wheel.scroll(0.0, -2.0, 1.0), (0, -2));
+        assert_eq!(wheel.scroll(3.0, 0.0, 1.0), (3, 0));
+        assert_eq!(wheel.scroll(-1.0, 1.0, 3.0), (-3, 3));
+    }
+
+    #[test]
+    fn wheel_accumulates_fractions() {
+        let mut wheel = Wheel::default();
+        assert_eq!(wheel.scroll(0.0, 0.4, 1.0), (0, 0));
+        assert_eq!(wheel.scroll(0.0, 0.4, 1.0), (0, 0));
+        assert_eq!(wheel.scroll(0.0, 0.4, 1.0), (0, 1));
+        assert_eq!(wheel.scroll(0.25, 0.0, 2.0), (0, 0));
+        assert_eq!(wheel.scroll(0.25, 0.0, 2.0), (1, 0));
+    }
+
+    #[test]
+    fn wheel_reversal_drops_the_remainder() {
+        let mut wheel = Wheel::default();
+        assert_eq!(wheel.scroll(0.0, 0.9, 1.0), (0, 0));
+        assert_eq!(wheel.scroll(0.0, -0.5, 1.0), (0, 0));
+        assert_eq!(wheel.scroll(0.0, -0.5, 1.0), (0, -1));
+    }
+
+    #[test]
+    fn hover_disabled() {
+        let mut hover = Hover::default();
+        hover.motion(1, 1);
+        assert_eq!(hover.poll(Instant::now()), None);
+    }
+
+    #[test]
+    fn hover_only_sends_new_cells() {
+        let mut hover = Hover::default();
+        hover.set_enabled(true);
+        let start = Instant::now();
+        hover.motion(1, 1);
+        assert_eq!(hover.poll(start), Some((1, 1)));
+        assert_eq!(hover.poll(start + MOVE_INTERVAL), None);
+        hover.motion(1, 1);
+        assert_eq!(hover.poll(start + MOVE_INTERVAL), None);
+        hover.motion(1, 2);
+        assert_eq!(hover.poll(start + MOVE_INTERVAL), Some((1, 2)));
+    }
+
+    #[test]
+    fn hover_is_throttled_to_the_latest_cell() {
+        let mut hover = Hover::default();
+        hover.set_enabled(true);
+        let start = Instant::now();
+        hover.motion(1, 1);
+        assert_eq!(hover.poll(start), Some((1, 1)));
+        hover.motion(1, 2);
+        hover.motion(1, 3);
+        assert_eq!(hover.poll(start + Duration::from_millis(5)), None);
+        hover.motion(1, 4);
+        assert_eq!(hover.poll(start + MOVE_INTERVAL), Some((1, 4)));
+    }
+
+    #[test]
+    fn hover_returning_to_the_sent_cell_cancels_the_pending_move() {
+        let mut hover = Hover::default();
+        hover.set_enabled(true);
+        let start = Instant::now();
+        hover.motion(1, 1);
+        assert_eq!(hover.poll(start), Some((1, 1)));
+        hover.motion(1, 2);
+        hover.motion(1, 1);
+        assert_eq!(hover.poll(start + MOVE_INTERVAL), None);
+    }
+}
@@ -1,9 +1,20 @@
 extern crate neovim_lib;
 
-use neovim_lib::{Handler, Neovim, NeovimApi, RequestHandler, Session, UiAttachOptions, Value};
-use std::env::Args;
-use std::process::Command;
+use neovim_lib::{Handler, Neovim, NeovimApi, RequestHandler, Session, Value};
+use std::env::{self, Args};
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use crate::api::{mouse_input_keys, ApiInfo};
+use crate::coalesce::coalesce;
+use crate::health::{self, HealthInfo};
+use crate::keyboard::escape_text;
 
 #[derive(Debug)]
 pub struct GridCell {
@@ -31,6 +42,17 @@ pub struct GridScroll {
     pub cols: i64,
 }
 
+#[derive(Debug)]
+pub struct WinFloatPos {
+    pub grid: i64,
+    pub anchor: String,
+    pub anchor_grid: i64,
+    pub anchor_row: f64,
+    pub anchor_col: f64,
+    pub focusable: bool,
+    pub zindex: i64,
+}
+
 #[derive(Debug, Default)]
 pub struct Highlight {
     pub fg: i64,
@@ -42,7 +64,12 @@ pub struct Highlight {
     pub strikethrough: bool,
     pub underline: bool,
     pub undercurl: bool,
+    pub underdouble: bool,
+    pub underdotted: bool,
+    pub underdashed: bool,
     pub blend: i64,
+    /// The UI highlight groups the highlight is made of, like "StatusLine", from `ext_hlstate`.
+    pub ui_groups: Vec<String>,
 }
 
 impl Highlight {
@@ -68,13 +95,80 @@ pub struct ModeInfo {
     // TODO mouse_shape (not yet implemented in nvim)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum NvimMode {
     Normal,
     Insert,
+    Visual,
     Command,
 }
 
+impl NvimMode {
+    /// Parses a mode name from `mode_change`, as listed in `:help 'guicursor'`.
+    fn from_name(name: &str) -> Self {
+        if name.starts_with("visual") {
+            Self::Visual
+        } else if name.starts_with("cmdline") {
+            Self::Command
+        } else if name == "insert" || name == "replace" {
+            Self::Insert
+        } else {
+            Self::Normal
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum OptionValue {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+impl OptionValue {
+    fn from_value(v: &Value) -> Option<Self> {
+        match v {
+            Value::Boolean(b) => Some(Self::Bool(*b)),
+            Value::Integer(_) => v.as_i64().map(Self::Int),
+            Value::String(_) => v.as_str().map(|s| Self::Str(s.into())),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> bool {
+        match self {
+            Self::Bool(b) => *b,
+            Self::Int(i) => *i != 0,
+            Self::Str(s) => !s.is_empty(),
+        }
+    }
+}
+
+/// Why nvim stopped, for the error screen.
+#[derive(Debug)]
+pub struct Failure {
+    pub reason: String,
+    /// The last lines nvim wrote to stderr.
+    pub stderr: Vec<String>,
+}
+
+/// `nvim_ui_attach` options nvim-sdl uses, if nvim supports them.
+const UI_OPTIONS: &[&str] = &["rgb", "ext_linegrid", "ext_multigrid", "ext_hlstate"];
+
+/// Options nvim-sdl needs that nvim doesn't send with `option_set`. Autocommands send them as
+/// `nvim_sdl_option` notifications instead.
+const WATCHED_OPTIONS: &[&str] = &["mousescroll"];
+
+/// How many lines of nvim's stderr a `Failure` keeps.
+const STDERR_LINES: usize = 10;
+
 #[derive(Debug)]
 pub enum NvimEvent {
     GridLine(Vec<GridLine>),
@@ -88,8 +182,19 @@ pub enum NvimEvent {
     ModeInfoSet(ModeInfo),
     HighlightAttrDefine { id: i64, hl: Highlight },
     GridResize { grid: i64, cols: i64, rows: i64 },
+    GridDestroy(i64),
+    WinPos { grid: i64, row: i64, col: i64, width: i64, height: i64 },
+    WinFloatPos(WinFloatPos),
+    WinHide(i64),
+    MsgSetPos { grid: i64, row: i64 },
+    OptionSet { name: String, value: OptionValue },
+    /// Text yanked for `ClientEvent::Copy`, to put on the clipboard.
+    Copied(String),
+    /// nvim failed to start or exited with an error.
+    Failed(Failure),
 }
 
+#[derive(Debug, PartialEq)]
 pub enum ClientEvent {
     Text(String),
     Mouse {
@@ -99,20 +204,108 @@ pub enum ClientEvent {
         grid: i64,
         row: i64,
         col: i64,
+        /// How many times to repeat the event, for wheel events merged by `coalesce`.
+        count: i64,
     },
     WindowResize {
         cols: i64,
         rows: i64,
     },
+    Paste(String),
+    /// The window gained (`true`) or lost focus.
+    Focus(bool),
+    /// An error message to show in nvim.
+    Error(String),
+    /// An Ex command to run as if typed, without waiting for it to finish.
+    Command(String),
+    /// Yank the visual selection and send it back as `NvimEvent::Copied`.
+    Copy,
 }
 
 pub struct NvimBridge {
     tx: Sender<NvimEvent>,
+    /// The process nvim was started as, which may be a wrapper that runs nvim.
+    child: Option<Child>,
+    stderr_path: PathBuf,
+    health: Arc<Mutex<HealthInfo>>,
 }
 
 impl NvimBridge {
-    pub fn new(tx: Sender<NvimEvent>) -> Self {
-        Self { tx }
+    pub fn new(
+        tx: Sender<NvimEvent>,
+        child: Option<Child>,
+        stderr_path: PathBuf,
+        health: Arc<Mutex<HealthInfo>>,
+    ) -> Self {
+        Self {
+            tx,
+            child,
+            stderr_path,
+            health,
+        }
+    }
+}
+
+fn stderr_tail(path: &Path) -> Vec<String> {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    let lines: Vec<String> = contents.lines().map(String::from).collect();
+    lines[lines.len().saturating_sub(STDERR_LINES)..].to_vec()
+}
+
+/// Waits for nvim to exit. Returns how it exited if that was an error, or `None` if it quit
+/// normally.
+fn wait_for_exit(child: Option<&mut Child>) -> Option<String> {
+    let status = match child.map(Child::wait) {
+        Some(Ok(status)) => status,
+        _ => return Some("nvim exited, but its exit status is unknown".into()),
+    };
+    match status.code() {
+        Some(0) => None,
+        Some(code) => Some(format!("nvim exited with status {}", code)),
+        None => Some(killed(&status)),
+    }
+}
+
+/// Describes an exit without a status code, which on unix means a signal.
+#[cfg(unix)]
+fn killed(status: &ExitStatus) -> String {
+    use std::os::unix::process::ExitStatusExt;
+    match status.signal() {
+        Some(signal) => format!("nvim was killed by signal {}", signal),
+        None => "nvim stopped unexpectedly".into(),
+    }
+}
+
+#[cfg(not(unix))]
+fn killed(_status: &ExitStatus) -> String {
+    "nvim stopped unexpectedly".into()
+}
+
+/// Tells nvim who its UI is, for `:checkhealth` and plugins.
+fn client_info() -> Vec<Value> {
+    let version_part = |part: &str| Value::from(part.parse::<i64>().unwrap_or(0));
+    let version = vec![
+        ("major".into(), version_part(env!("CARGO_PKG_VERSION_MAJOR"))),
+        ("minor".into(), version_part(env!("CARGO_PKG_VERSION_MINOR"))),
+        ("patch".into(), version_part(env!("CARGO_PKG_VERSION_PATCH"))),
+    ];
+    let attributes = vec![(
+        "website".into(),
+        "https://github.com/paulpage/nvim-sdl".into(),
+    )];
+    vec![
+        "nvim-sdl".into(),
+        Value::Map(version),
+        "ui".into(),
+        Value::Map(Vec::new()),
+        Value::Map(attributes),
+    ]
+}
+
+/// Prints a failed request. Requests fail when nvim exits, which `handle_close` reports.
+fn report<T, E: Display>(result: Result<T, E>) {
+    if let Err(e) = result {
+        println!("Request failed: {}", e);
     }
 }
 
@@ -164,8 +357,14 @@ fn pretty_print_value(v: &Value, indent_level: usize) {
 
 impl RequestHandler for NvimBridge {
     fn handle_request(&mut self, name: &str, _args: Vec<Value>) -> Result<Value, Value> {
-        println!("Unknown request: {}", name);
-        Err("Unkown request".into())
+        match name {
+            // From :checkhealth nvim-sdl.
+            "nvim_sdl_health" => Ok(self.health.lock().unwrap().to_value()),
+            _ => {
+                println!("Unknown request: {}", name);
+                Err("Unkown request".into())
+            }
+        }
     }
 }
 
@@ -284,8 +483,13 @@ impl Handler for NvimBridge {
                                     // println!("MODE INFO SET: {:?}", event);
                                 }
                                 "mode_change" => {
-                                    // mode_args = event[0].as_array().unwrap();
-                                    // println!("MODE CHANGE: {:?}", event);
+                                    let mode_args = event.last().and_then(|e| e.as_array());
+                                    if let Some(mode_args) = mode_args {
+                                        let name = mode_args[0].as_str().unwrap_or("");
+                                        self.tx
+                                            .send(NvimEvent::ModeChange(NvimMode::from_name(name)))
+                                            .unwrap();
+                                    }
                                 }
                                 "hl_attr_define" => {
                                     for hl_definition in event.iter().skip(1) {
@@ -322,12 +526,38 @@ impl Handler for NvimBridge {
                                                 "undercurl" => {
                                                     hl.undercurl = v.as_bool().unwrap();
                                                 }
+                                                // Called "underlineline" before nvim 0.8.
+                                                "underdouble" | "underlineline" => {
+                                                    hl.underdouble = v.as_bool().unwrap();
+                                                }
+                                                "underdotted" => {
+                                                    hl.underdotted = v.as_bool().unwrap();
+                                                }
+                                                "underdashed" => {
+                                                    hl.underdashed = v.as_bool().unwrap();
+                                                }
                                                 "blend" => {
                                                     hl.blend = v.as_i64().unwrap();
                                                 }
                                                 _ => {}
                                             }
                                         }
+                                        // With ext_hlstate, what the highlight is made of.
+                                        let info = args.get(3).and_then(|i| i.as_array());
+                                        for item in info.into_iter().flatten() {
+                                            let field = |name| {
+                                                item.as_map()?
+                                                    .iter()
+                                                    .find(|(k, _)| k.as_str() == Some(name))?
+                                                    .1
+                                                    .as_str()
+                                            };
+                                            if let (Some("ui"), Some(group)) =
+                                                (field("kind"), field("ui_name"))
+                                            {
+                                                hl.ui_groups.push(group.into());
+                                            }
+                                        }
                                         self.tx
                                             .send(NvimEvent::HighlightAttrDefine { id, hl })
                                             .unwrap();
@@ -341,10 +571,15 @@ impl Handler for NvimBridge {
                                     // }
                                 }
                                 "option_set" => {
-                                    // for arg in event {
-                                    // pretty_print_value(arg, 0);
-                                    // println!();
-                                    // }
+                                    for args in event.iter().skip(1) {
+                                        let args = args.as_array().unwrap();
+                                        let name = args[0].as_str().unwrap_or("").to_string();
+                                        if let Some(value) = OptionValue::from_value(&args[1]) {
+                                            self.tx
+                                                .send(NvimEvent::OptionSet { name, value })
+                                                .unwrap();
+                                        }
+                                    }
                                 }
                                 "grid_resize" => {
                                     let args = event[1].as_array().unwrap();
@@ -356,6 +591,64 @@ impl Handler for NvimBridge {
                                         })
                                         .unwrap();
                                 }
+                                "grid_destroy" => {
+                                    for args in event.iter().skip(1) {
+                                        let grid = args.as_array().unwrap()[0].as_i64().unwrap();
+                                        self.tx.send(NvimEvent::GridDestroy(grid)).unwrap();
+                                    }
+                                }
+                                "win_pos" => {
+                                    for args in event.iter().skip(1) {
+                                        let args = args.as_array().unwrap();
+                                        self.tx
+                                            .send(NvimEvent::WinPos {
+                                                grid: args[0].as_i64().unwrap(),
+                                                row: args[2].as_i64().unwrap(),
+                                                col: args[3].as_i64().unwrap(),
+                                                width: args[4].as_i64().unwrap(),
+                                                height: args[5].as_i64().unwrap(),
+                                            })
+                                            .unwrap();
+                                    }
+                                }
+                                "win_float_pos" => {
+                                    for args in event.iter().skip(1) {
+                                        let args = args.as_array().unwrap();
+                                        self.tx
+                                            .send(NvimEvent::WinFloatPos(WinFloatPos {
+                                                grid: args[0].as_i64().unwrap(),
+                                                anchor: args[2].as_str().unwrap_or("NW").into(),
+                                                anchor_grid: args[3].as_i64().unwrap(),
+                                                anchor_row: args[4].as_f64().unwrap_or(0.0),
+                                                anchor_col: args[5].as_f64().unwrap_or(0.0),
+                                                focusable: args[6].as_bool().unwrap_or(true),
+                                                zindex: args
+                                                    .get(7)
+                                                    .and_then(|z| z.as_i64())
+                                                    .unwrap_or(50),
+                                            }))
+                                            .unwrap();
+                                    }
+                                }
+                                "win_hide" | "win_close" => {
+                                    for args in event.iter().skip(1) {
+                                        let grid = args.as_array().unwrap()[0].as_i64().unwrap();
+                                        self.tx.send(NvimEvent::WinHide(grid)).unwrap();
+                                    }
+                                }
+                                "msg_set_pos" => {
+                                    for args in event.iter().skip(1) {
+                                        let args = args.as_array().unwrap();
+                                        self.tx
+                                            .send(NvimEvent::MsgSetPos {
+                                                grid: args[0].as_i64().unwrap(),
+                                                row: args[1].as_i64().unwrap(),
+                                            })
+                                            .unwrap();
+                                    }
+                                }
+                                // Scroll positions and external windows aren't used.
+                                "win_viewport" | "win_viewport_margins" | "win_external_pos" => {}
                                 _ => {
                                     println!("Unknown redraw: {:?}", event_name);
                                 }
@@ -364,39 +657,174 @@ impl Handler for NvimBridge {
                     }
                 }
             }
+            // From the autocommands for `WATCHED_OPTIONS`.
+            "nvim_sdl_option" => {
+                let name = args
+                    .first()
+                    .and_then(|n| n.as_str())
+                    .unwrap_or("")
+                    .to_string();
+                if let Some(value) = args.get(1).and_then(OptionValue::from_value) {
+                    self.tx.send(NvimEvent::OptionSet { name, value }).unwrap();
+                }
+            }
             _ => println!("Unknown notify: {} {:?}", name, args),
         }
     }
     fn handle_close(&mut self) {
-        self.tx.send(NvimEvent::Close).unwrap();
+        match wait_for_exit(self.child.as_mut()) {
+            None => {
+                let _ = fs::remove_file(&self.stderr_path);
+                self.tx.send(NvimEvent::Close).unwrap();
+            }
+            Some(reason) => {
+                let stderr = stderr_tail(&self.stderr_path);
+                self.tx
+                    .send(NvimEvent::Failed(Failure { reason, stderr }))
+                    .unwrap();
+            }
+        }
+    }
+}
+
+/// Sends the current values of `WATCHED_OPTIONS` and has nvim notify nvim-sdl when they
+/// change, including when the user's config sets them after the UI attached. Options this nvim
+/// doesn't have are skipped.
+fn watch_options(nvim: &mut Neovim, tx: &Sender<NvimEvent>, channel: i64) {
+    for &name in WATCHED_OPTIONS {
+        let value = match nvim.call_function("nvim_get_option", vec![name.into()]) {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        if let Some(value) = OptionValue::from_value(&value) {
+            tx.send(NvimEvent::OptionSet {
+                name: name.into(),
+                value,
+            })
+            .unwrap();
+        }
+        let notify = format!(
+            "call rpcnotify({}, 'nvim_sdl_option', '{}', &{})",
+            channel, name, name
+        );
+        for event in &[format!("OptionSet {}", name), "VimEnter *".to_string()] {
+            let command = format!("autocmd {} {}", event, notify);
+            report(nvim.call_function("nvim_command", vec![command.into()]));
+        }
     }
 }
 
-pub fn start(tx: Sender<NvimEvent>, rx: Receiver<ClientEvent>, args: Args) {
+/// Runs nvim and forwards client events to it. `queue_depth` is set to the number of events
+/// that were waiting each time the loop wakes up.
+///
+/// Failures are sent as `NvimEvent::Failed`. The receiver is kept until the UI drops its
+/// sender, so sending to a dead nvim never fails.
+pub fn start(
+    tx: Sender<NvimEvent>,
+    rx: Receiver<ClientEvent>,
+    args: Args,
+    queue_depth: Arc<AtomicUsize>,
+    health: Arc<Mutex<HealthInfo>>,
+) {
+    let stderr_path = env::temp_dir().join(format!("nvim-sdl-{}.log", process::id()));
     let mut cmd = Command::new("nvim");
     cmd.arg("--embed");
+    if let Some(dir) = health::install_runtime() {
+        cmd.arg("--cmd").arg(health::add_to_runtimepath(&dir));
+    }
     let args: Vec<String> = args.collect();
     for arg in &args[1..] {
         cmd.arg(arg);
     }
-    let bridge = NvimBridge::new(tx);
-    let mut session = Session::new_child_cmd(&mut cmd).unwrap();
+    match File::create(&stderr_path) {
+        Ok(file) => {
+            cmd.stderr(Stdio::from(file));
+        }
+        Err(e) => println!("Failed to capture nvim's stderr: {}", e),
+    }
+    let mut session = match Session::new_child_cmd(&mut cmd) {
+        Ok(session) => session,
+        Err(e) => {
+            tx.send(NvimEvent::Failed(Failure {
+                reason: format!("Failed to start nvim: {}", e),
+                stderr: Vec::new(),
+            }))
+            .unwrap();
+            for _ in rx {}
+            return;
+        }
+    };
+    let child = session.take_process();
+    let bridge = NvimBridge::new(tx.clone(), child, stderr_path, health.clone());
     session.start_event_loop_handler(bridge);
     let mut nvim = Neovim::new(session);
-    let mut ui_opts = UiAttachOptions::new();
-    ui_opts.set_rgb(true);
-    ui_opts.set_linegrid_external(true);
-    ui_opts.set_popupmenu_external(false);
-    ui_opts.set_tabline_external(false);
-    ui_opts.set_cmdline_external(false);
-    ui_opts.set_wildmenu_external(false);
-    nvim.ui_attach(80, 30, &ui_opts).unwrap();
-
-    loop {
-        if let Ok(s) = rx.recv() {
-            match s {
+    let info = match nvim.call_function("nvim_get_api_info", Vec::new()) {
+        Ok(info) => info,
+        Err(e) => {
+            // Most likely nvim exited, which `handle_close` reports with its status and stderr.
+            println!("Failed to get nvim's API info: {}", e);
+            for _ in rx {}
+            return;
+        }
+    };
+    let api_info = match ApiInfo::parse(&info) {
+        Some(api_info) => api_info,
+        None => {
+            tx.send(NvimEvent::Failed(Failure {
+                reason: "nvim sent API info nvim-sdl can't read".into(),
+                stderr: Vec::new(),
+            }))
+            .unwrap();
+            for _ in rx {}
+            return;
+        }
+    };
+    // Everything is drawn from grid_line events.
+    if !api_info.has_ui_option("ext_linegrid") {
+        let (major, minor, patch) = api_info.version;
+        tx.send(NvimEvent::Failed(Failure {
+            reason: format!(
+                "nvim {}.{}.{} is too old, nvim-sdl needs 0.4 or newer",
+                major, minor, patch
+            ),
+            stderr: Vec::new(),
+        }))
+        .unwrap();
+        for _ in rx {}
+        return;
+    }
+    if api_info.has_function("nvim_set_client_info") {
+        report(nvim.call_function("nvim_set_client_info", client_info()));
+    }
+    // Tells the health check where to send its request.
+    report(nvim.call_function(
+        "nvim_set_var",
+        vec!["nvim_sdl_channel".into(), api_info.channel_id.into()],
+    ));
+    let ui_options: Vec<&str> = UI_OPTIONS
+        .iter()
+        .cloned()
+        .filter(|name| api_info.has_ui_option(name))
+        .collect();
+    health.lock().unwrap().ui_options = ui_options.iter().map(|&name| name.into()).collect();
+    let ui_options = ui_options
+        .iter()
+        .map(|&name| (name.into(), true.into()))
+        .collect();
+    report(nvim.call_function(
+        "nvim_ui_attach",
+        vec![80.into(), 30.into(), Value::Map(ui_options)],
+    ));
+    watch_options(&mut nvim, &tx, api_info.channel_id);
+
+    while let Ok(first) = rx.recv() {
+        // Everything that arrived while the last batch was being sent goes out together.
+        let events: Vec<ClientEvent> = iter::once(first).chain(rx.try_iter()).collect();
+        queue_depth.store(events.len(), Ordering::Relaxed);
+        for event in coalesce(events) {
+            match event {
                 ClientEvent::Text(s) => {
-                    nvim.input(&s).unwrap();
+                    report(nvim.input(&s));
                 }
                 ClientEvent::Mouse {
                     button,
@@ -405,27 +833,91 @@ pub fn start(tx: Sender<NvimEvent>, rx: Receiver<ClientEvent>, args: Args) {
                     grid,
                     row,
                     col,
+                    count,
                 } => {
-                    nvim.call_function(
-                        "nvim_input_mouse",
-                        vec![
-                            button.into(),
-                            action.into(),
-                            modifier.into(),
-                            grid.into(),
-                            row.into(),
-                            col.into(),
-                        ],
-                    )
-                    .unwrap();
+                    if !api_info.has_function("nvim_input_mouse") {
+                        // Older versions take mouse events as keys.
+                        if let Some(keys) = mouse_input_keys(&button, &action, &modifier, row, col)
+                        {
+                            report(nvim.input(&keys.repeat(count as usize)));
+                        }
+                        continue;
+                    }
+                    let args: Vec<Value> = vec![
+                        button.into(),
+                        action.into(),
+                        modifier.into(),
+                        grid.into(),
+                        row.into(),
+                        col.into(),
+                    ];
+                    if count == 1 {
+                        report(nvim.call_function("nvim_input_mouse", args));
+                    } else if api_info.has_function("nvim_call_atomic") {
+                        let call = Value::Array(vec!["nvim_input_mouse".into(), args.into()]);
+                        let calls = vec![call; count as usize];
+                        report(nvim.call_function("nvim_call_atomic", vec![calls.into()]));
+                    } else {
+                        for _ in 0..count {
+                            report(nvim.call_function("nvim_input_mouse", args.clone()));
+                        }
+                    }
                 }
                 ClientEvent::WindowResize { cols, rows } => {
-                    nvim.ui_try_resize(cols, rows).unwrap();
+                    report(nvim.ui_try_resize(cols, rows));
                     // nvim.call_function(
                     //     "nvim_ui_try_resize",
                     //     vec![cols.into(), rows.into()],
                     // ).unwrap();
                 }
+                ClientEvent::Paste(text) => {
+                    if api_info.has_function("nvim_paste") {
+                        report(nvim.call_function(
+                            "nvim_paste",
+                            vec![text.into(), true.into(), (-1).into()],
+                        ));
+                    } else {
+                        report(nvim.input(&escape_text(&text)));
+                    }
+                }
+                ClientEvent::Focus(gained) => {
+                    // nvim_ui_set_focus is new in nvim 0.9, older versions only get the autocmd.
+                    if api_info.has_function("nvim_ui_set_focus") {
+                        report(nvim.call_function("nvim_ui_set_focus", vec![gained.into()]));
+                    } else {
+                        let event = if gained { "FocusGained" } else { "FocusLost" };
+                        let command = format!("doautocmd <nomodeline> {}", event);
+                        report(nvim.call_function("nvim_command", vec![command.into()]));
+                    }
+                }
+                ClientEvent::Error(message) => {
+                    report(nvim.call_function("nvim_err_writeln", vec![message.into()]));
+                }
+                ClientEvent::Command(command) => {
+                    // nvim_command would block the connector until a confirm dialog is answered,
+                    // with the answer stuck behind it. <Cmd> runs the command without leaving
+                    // the current mode; it is new in nvim 0.5, like nvim_exec.
+                    let command = escape_text(&command);
+                    let keys = if api_info.has_function("nvim_exec") {
+                        format!("<Cmd>{}<CR>", command)
+                    } else {
+                        format!("<C-\\><C-N>:{}<CR>", command)
+                    };
+                    report(nvim.input(&keys));
+                }
+                ClientEvent::Copy => {
+                    // Yanked into the unnamed register, which works without a clipboard
+                    // provider. The keys are handled before the next request.
+                    report(nvim.input("y"));
+                    let getreg = vec!["getreg".into(), vec![Value::from("\"")].into()];
+                    match nvim.call_function("nvim_call_function", getreg) {
+                        Ok(text) => {
+                            let text = text.as_str().unwrap_or("").to_string();
+                            tx.send(NvimEvent::Copied(text)).unwrap();
+                        }
+                        Err(e) => println!("Failed to get the yanked text: {}", e),
+                    }
+                }
             }
         }
     }
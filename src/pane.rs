@@ -1,17 +1,29 @@
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
-use sdl2::render::{Texture, TextureQuery, WindowCanvas};
-use sdl2::ttf::Font;
+use sdl2::render::{BlendMode, Texture, TextureQuery, WindowCanvas};
+use sdl2::surface::Surface;
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::time::{Instant, Duration};
 
+use crate::cluster::{clusters, is_rtl};
+use crate::decoration::{self, Decoration, LineMetrics};
+use crate::font::{FontStyle, Fonts};
+use crate::grid::Grid;
 use crate::neovim_connector::Highlight;
+use crate::shaping::{self, Bitmap, FontFeature, ShapedGlyph, ShapedRun};
+
+/// Shaped runs kept before the cache starts over, as every edit makes new ones.
+const SHAPE_CACHE_SIZE: usize = 4096;
+/// Rasterized glyphs kept before the cache starts over, as each color makes new ones.
+const GLYPH_CACHE_SIZE: usize = 4096;
 
 #[derive(Hash, PartialEq)]
 struct FontCacheKey {
     c: String,
     color: Color,
+    font: usize,
+    style: FontStyle,
 }
 
 #[derive(Clone)]
@@ -35,6 +47,25 @@ struct FontCacheEntry {
     h: u32,
 }
 
+#[derive(Hash, PartialEq, Eq)]
+struct GlyphCacheKey {
+    font: usize,
+    id: u16,
+    color: Color,
+}
+
+/// A rasterized glyph from shaping, with its offset from the pen on the baseline.
+struct GlyphCacheEntry {
+    texture: Texture,
+    left: i32,
+    top: i32,
+    w: u32,
+    h: u32,
+}
+
+/// A run of cells shaped together, by font, style and cell texts.
+type ShapeCacheKey = (usize, FontStyle, Vec<String>);
+
 impl Eq for FontCacheKey {}
 
 pub struct Pane<'a> {
@@ -50,9 +81,17 @@ pub struct Pane<'a> {
     bg_color: Color,
     fg_color: Color,
     special_color: Color,
-    col_width: u32,
-    pub font: Font<'a, 'static>,
+    pub col_width: u32,
+    line_metrics: LineMetrics,
+    pub fonts: Fonts<'a>,
+    /// The highlight attributes that change the font. Others are drawn in regular.
+    pub enabled_styles: FontStyle,
+    /// OpenType features runs are shaped with.
+    pub font_features: Vec<FontFeature>,
     font_cache: HashMap<FontCacheKey, Rc<FontCacheEntry>>,
+    /// `None` for glyphs without an outline, like spaces.
+    glyph_cache: HashMap<GlyphCacheKey, Option<Rc<GlyphCacheEntry>>>,
+    shape_cache: HashMap<ShapeCacheKey, ShapedRun>,
 }
 
 fn parse_color(c: i64) -> Color {
@@ -63,6 +102,46 @@ fn parse_color(c: i64) -> Color {
     )
 }
 
+/// Returns the opacity of a background with `blend`, which goes from 0 for opaque to 100 for
+/// fully transparent.
+fn blend_alpha(blend: i64) -> u8 {
+    (255 * (100 - blend.clamp(0, 100)) / 100) as u8
+}
+
+/// Makes a surface of `color` with the glyph's coverage as alpha.
+fn glyph_surface(bitmap: &Bitmap, color: Color) -> Surface<'static> {
+    let mut surface = Surface::new(bitmap.width, bitmap.height, PixelFormatEnum::RGBA32).unwrap();
+    let pitch = surface.pitch() as usize;
+    let width = bitmap.width as usize;
+    surface.with_lock_mut(|pixels| {
+        for (i, coverage) in bitmap.coverage.iter().enumerate() {
+            let offset = i / width * pitch + i % width * 4;
+            let alpha = (coverage * f32::from(color.a)).round() as u8;
+            pixels[offset..offset + 4].copy_from_slice(&[color.r, color.g, color.b, alpha]);
+        }
+    });
+    surface
+}
+
+/// Returns the text and background colors of a cell, given the default colors. Like in nvim's
+/// TUI, `reverse` swaps them after filling in defaults, so default-colored text turns into the
+/// default foreground as background. The background is `None` where the default one shows.
+fn resolve_colors(hl: Option<&Highlight>, fg: Color, bg: Color) -> (Color, Option<Color>) {
+    let hl = match hl {
+        Some(hl) => hl,
+        None => return (fg, None),
+    };
+    let or_default = |c: i64, default: Color| if c == -1 { default } else { parse_color(c) };
+    let text = or_default(hl.fg, fg);
+    if hl.reverse {
+        (or_default(hl.bg, bg), Some(text))
+    } else if hl.bg == -1 {
+        (text, None)
+    } else {
+        (text, Some(parse_color(hl.bg)))
+    }
+}
+
 impl<'a> Pane<'a> {
     pub fn set_colors(&mut self, fg: i64, bg: i64, special: i64) {
         self.bg_color = parse_color(bg);
@@ -70,7 +149,7 @@ impl<'a> Pane<'a> {
         self.special_color = parse_color(special);
     }
 
-    pub fn new(font: Font<'a, 'static>) -> Self {
+    pub fn new(fonts: Fonts<'a>) -> Self {
         Pane {
             x: 0,
             y: 0,
@@ -78,97 +157,451 @@ impl<'a> Pane<'a> {
             h: 0,
             scroll_idx: 0,
             scroll_offset: 0,
-            row_height: font.height() as u32,
-            col_width: font.size_of_char('W').unwrap().0,
+            row_height: fonts.primary().height() as u32,
+            col_width: fonts.primary().size_of_char('W').unwrap().0,
+            line_metrics: fonts.line_metrics(),
             cursor_row: 0,
             cursor_col: 0,
             bg_color: Color::RGB(0, 0, 0),
             fg_color: Color::RGB(0, 255, 0),
             special_color: Color::RGB(0, 0, 255),
-            font,
+            fonts,
+            enabled_styles: FontStyle {
+                bold: true,
+                italic: true,
+            },
+            font_features: Vec::new(),
             font_cache: HashMap::new(),
+            glyph_cache: HashMap::new(),
+            shape_cache: HashMap::new(),
         }
     }
 
+    /// Replaces the fonts, e.g. after 'guifont' changed. The cell size follows the primary font.
+    pub fn set_fonts(&mut self, mut fonts: Fonts<'a>) {
+        fonts.keep_system_fonts(&self.fonts);
+        self.fonts = fonts;
+        self.update_cell_size();
+    }
+
+    /// Reloads the fonts at another size, e.g. after zooming.
+    pub fn set_font_size(&mut self, size: u16) -> Result<(), String> {
+        self.fonts.resize(size)?;
+        self.update_cell_size();
+        Ok(())
+    }
+
+    fn update_cell_size(&mut self) {
+        let font = self.fonts.primary();
+        self.row_height = font.height() as u32;
+        self.col_width = font.size_of_char('W').unwrap().0;
+        self.line_metrics = self.fonts.line_metrics();
+        self.clear_font_cache();
+    }
+
+    fn clear_font_cache(&mut self) {
+        // With `unsafe_textures`, textures are only freed when destroyed explicitly.
+        for (_, entry) in self.font_cache.drain() {
+            if let Ok(entry) = Rc::try_unwrap(entry) {
+                unsafe { entry.texture.destroy() };
+            }
+        }
+        self.clear_glyph_cache();
+        self.shape_cache.clear();
+    }
+
+    fn clear_glyph_cache(&mut self) {
+        for (_, entry) in self.glyph_cache.drain() {
+            if let Some(Ok(entry)) = entry.map(Rc::try_unwrap) {
+                unsafe { entry.texture.destroy() };
+            }
+        }
+    }
+
+    /// Draws lines of text in reverse video in the top right corner, on top of the grid.
+    pub fn draw_overlay(&mut self, canvas: &mut WindowCanvas, lines: &[String]) {
+        let (w, _) = canvas.output_size().unwrap();
+        let texture_creator = canvas.texture_creator();
+        for (i, line) in lines.iter().enumerate() {
+            let surface = self
+                .fonts
+                .primary()
+                .render(line)
+                .shaded(self.bg_color, self.fg_color)
+                .unwrap();
+            let texture = texture_creator
+                .create_texture_from_surface(&surface)
+                .unwrap();
+            let TextureQuery { width, height, .. } = texture.query();
+            let target = Rect::new(
+                w as i32 - width as i32,
+                i as i32 * self.row_height as i32,
+                width,
+                height,
+            );
+            canvas.copy(&texture, None, Some(target)).unwrap();
+            unsafe { texture.destroy() };
+        }
+    }
+
+    /// Clears the window and draws lines of text from the top left, instead of the grid.
+    pub fn draw_message(&mut self, canvas: &mut WindowCanvas, lines: &[String]) {
+        canvas.set_draw_color(self.bg_color);
+        canvas.clear();
+        let texture_creator = canvas.texture_creator();
+        for (i, line) in lines.iter().enumerate() {
+            // SDL_ttf can't render empty text.
+            if line.is_empty() {
+                continue;
+            }
+            let surface = self
+                .fonts
+                .primary()
+                .render(line)
+                .blended(self.fg_color)
+                .unwrap();
+            let texture = texture_creator
+                .create_texture_from_surface(&surface)
+                .unwrap();
+            let TextureQuery { width, height, .. } = texture.query();
+            let target = Rect::new(
+                self.col_width as i32,
+                (i as i32 + 1) * self.row_height as i32,
+                width,
+                height,
+            );
+            canvas.copy(&texture, None, Some(target)).unwrap();
+            unsafe { texture.destroy() };
+        }
+    }
+
+    /// Draws the underlines and strikethrough of a cell. Lines take the highlight's special
+    /// color, or the text color, except undercurls which fall back to the default special color.
+    fn draw_decorations(&self, canvas: &mut WindowCanvas, hl: &Highlight, fg: Color, cell: Rect) {
+        let decorations = [
+            (hl.underline, Decoration::Underline),
+            (hl.underdouble, Decoration::Underdouble),
+            (hl.undercurl, Decoration::Undercurl),
+            (hl.underdotted, Decoration::Underdotted),
+            (hl.underdashed, Decoration::Underdashed),
+            (hl.strikethrough, Decoration::Strikethrough),
+        ];
+        for &(_, decoration) in decorations.iter().filter(|(enabled, _)| *enabled) {
+            let color = match (hl.special, decoration) {
+                (-1, Decoration::Undercurl) => self.special_color,
+                (-1, _) => fg,
+                (special, _) => parse_color(special),
+            };
+            let rects = decoration::rects(
+                decoration,
+                &self.line_metrics,
+                cell.x(),
+                cell.y(),
+                cell.width(),
+            );
+            canvas.set_draw_color(color);
+            canvas.fill_rects(&rects).unwrap();
+        }
+    }
+
+    /// Draws grids bottom to top, each given with the screen position of its top left cell.
+    /// The first one is the default grid.
     pub fn draw(
         &mut self,
         canvas: &mut WindowCanvas,
-        text: &[Vec<TextCell>],
+        grids: &[(i64, i64, &Grid)],
         highlight_table: &HashMap<i64, Highlight>,
     ) {
-        let char_rect = Rect::new(0, 0, self.col_width, self.row_height);
-        let mut color = self.fg_color;
         canvas.set_draw_color(self.bg_color);
         canvas.clear();
+        canvas.set_blend_mode(BlendMode::Blend);
+        for (i, &(row, col, text)) in grids.iter().enumerate() {
+            let paint_default_bg = i > 0;
+            self.draw_grid(canvas, text, row, col, paint_default_bg, highlight_table);
+        }
+        canvas.set_blend_mode(BlendMode::None);
 
+        canvas.set_draw_color(self.fg_color);
+        let cursor_rect = Rect::new(
+            self.x + self.cursor_col * self.col_width as i32,
+            self.y + self.cursor_row * self.row_height as i32,
+            2,
+            self.row_height as u32,
+        );
+        canvas.fill_rect(cursor_rect).unwrap();
+    }
+
+    /// Draws a grid at a screen position. With `paint_default_bg`, for windows over the default
+    /// grid, cells without a background of their own get the default one, translucently where
+    /// the highlight has a `blend`, as for 'winblend'.
+    ///
+    /// Backgrounds go first, so glyphs reaching into the next cell aren't painted over. Cells
+    /// with the same highlight and font are shaped together for ligatures, except at the cursor,
+    /// and runs of right-to-left text for the joined forms of Arabic.
+    fn draw_grid(
+        &mut self,
+        canvas: &mut WindowCanvas,
+        text: &Grid,
+        row_offset: i64,
+        col_offset: i64,
+        paint_default_bg: bool,
+        highlight_table: &HashMap<i64, Highlight>,
+    ) {
+        let x = self.x + col_offset as i32 * self.col_width as i32;
+        let y = self.y + row_offset as i32 * self.row_height as i32;
         for (rownum, row) in text.iter().enumerate() {
-            for (colnum, col) in row.iter().enumerate() {
-                let last_color = color;
-                color = if highlight_table.contains_key(&col.hl_id) {
-                    let fg = highlight_table[&col.hl_id].fg;
-                    if fg == -1 {
-                        self.fg_color
-                    } else {
-                        parse_color(fg)
-                    }
+            let texts: Vec<&str> = row.iter().map(|cell| cell.text.as_str()).collect();
+            let clusters = clusters(&texts);
+            let mut cells = Vec::new();
+            for cluster in &clusters {
+                let hl_id = row[cluster.col].hl_id;
+                let hl = highlight_table.get(&hl_id);
+                let (color, mut background) = resolve_colors(hl, self.fg_color, self.bg_color);
+                if paint_default_bg {
+                    background = background.or(Some(self.bg_color));
+                }
+
+                let target = Rect::new(
+                    x + cluster.col as i32 * self.col_width as i32,
+                    y + rownum as i32 * self.row_height as i32,
+                    cluster.width as u32 * self.col_width,
+                    self.row_height,
+                );
+                if let Some(mut background) = background {
+                    background.a = blend_alpha(hl.map_or(0, |hl| hl.blend));
+                    canvas.set_draw_color(background);
+                    canvas.fill_rect(target).unwrap();
+                }
+
+                let style = hl
+                    .map(|hl| FontStyle {
+                        bold: hl.bold && self.enabled_styles.bold,
+                        italic: hl.italic && self.enabled_styles.italic,
+                    })
+                    .unwrap_or_default();
+                // nvim already decided the width, following 'ambiwidth' and 'emoji'.
+                let font = self.fonts.lookup(&cluster.text, style, cluster.width > 1);
+                // Wide characters and the empty text left where one was overwritten are
+                // drawn on their own.
+                let key = if cluster.width == 1 && !cluster.text.is_empty() {
+                    Some((hl_id, font, style, is_rtl(&cluster.text)))
                 } else {
-                    self.fg_color
+                    None
                 };
-                if color != last_color {
-                    canvas.set_draw_color(color);
-                }
+                cells.push((key, font, style, hl, color, target));
+            }
 
-                let key = FontCacheKey {
-                    c: col.text.to_string(),
-                    color,
+            let cursor_col = (self.cursor_col as i64 - col_offset) as usize;
+            let keys: Vec<_> = cells.iter().map(|&(key, ..)| key).collect();
+            // Words written right to left stay joined under the cursor.
+            let cursor = if self.cursor_row as i64 - row_offset == rownum as i64 {
+                clusters
+                    .iter()
+                    .position(|cluster| cluster.col == cursor_col)
+                    .filter(|&i| !matches!(keys[i], Some((.., true))))
+            } else {
+                None
+            };
+            for run in shaping::runs(&keys, cursor) {
+                let shaped = match keys[run.start] {
+                    Some((_, font, style, rtl)) if run.len() > 1 => {
+                        let texts: Vec<&str> = clusters[run.clone()]
+                            .iter()
+                            .map(|cluster| cluster.text.as_str())
+                            .collect();
+                        self.shape(font, style, &texts, rtl)
+                    }
+                    _ => ShapedRun::default(),
                 };
-                let tex = self.font_cache.get(&key).cloned().unwrap_or_else(|| {
-                    let surface = self
-                        .font
-                        .render(&col.text.to_string())
-                        .blended(color)
-                        .unwrap();
-                    let texture = canvas
-                        .texture_creator()
-                        .create_texture_from_surface(&surface)
-                        .unwrap();
-                    let TextureQuery { width, height, .. } = texture.query();
-                    let resource = Rc::new(FontCacheEntry {
-                        texture,
-                        w: width,
-                        h: height,
-                    });
-                    self.font_cache.insert(key, resource.clone());
-                    resource
+                for i in run.clone() {
+                    let (_, font, style, hl, color, target) = cells[i];
+                    let cell = i - run.start;
+                    if shaped.changed.get(cell) == Some(&true) {
+                        let glyphs: Vec<&ShapedGlyph> =
+                            shaped.glyphs.iter().filter(|g| g.cell == cell).collect();
+                        self.draw_glyphs(canvas, font, &glyphs, color, target);
+                    } else {
+                        let text = &clusters[i].text;
+                        self.draw_cluster(canvas, text, font, style, color, target);
+                    }
+                    if let Some(hl) = hl {
+                        self.draw_decorations(canvas, hl, color, target);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Shapes a run of cells, from the cache if it was shaped before.
+    fn shape(&mut self, font: usize, style: FontStyle, texts: &[&str], rtl: bool) -> ShapedRun {
+        let key = (font, style, texts.iter().map(|&t| t.to_string()).collect());
+        if let Some(shaped) = self.shape_cache.get(&key) {
+            return shaped.clone();
+        }
+        if self.shape_cache.len() >= SHAPE_CACHE_SIZE {
+            self.shape_cache.clear();
+        }
+        let shaped = self
+            .fonts
+            .shape(font, style, texts, &self.font_features, rtl);
+        self.shape_cache.insert(key, shaped.clone());
+        shaped
+    }
+
+    /// Draws a grapheme cluster with SDL_ttf.
+    fn draw_cluster(
+        &mut self,
+        canvas: &mut WindowCanvas,
+        text: &str,
+        font: usize,
+        style: FontStyle,
+        color: Color,
+        target: Rect,
+    ) {
+        // SDL_ttf can't render empty text, which is left where a wide character was
+        // overwritten.
+        if text.is_empty() {
+            return;
+        }
+        let key = FontCacheKey {
+            c: text.to_string(),
+            color,
+            font,
+            style,
+        };
+        let tex = match self.font_cache.get(&key) {
+            Some(tex) => tex.clone(),
+            None => {
+                let surface = self.fonts.render(font, style, &key.c, color);
+                let texture = canvas
+                    .texture_creator()
+                    .create_texture_from_surface(&surface)
+                    .unwrap();
+                let TextureQuery { width, height, .. } = texture.query();
+                let resource = Rc::new(FontCacheEntry {
+                    texture,
+                    w: width,
+                    h: height,
                 });
-                let texture = &tex.texture;
+                self.font_cache.insert(key, resource.clone());
+                resource
+            }
+        };
+        // Glyphs wider than their cells, like an ambiguous-width character in a font that
+        // draws it wide, are squeezed in rather than cut off.
+        let height = tex.h.min(self.row_height);
+        let source = Rect::new(0, 0, tex.w, height);
+        let width = tex.w.min(target.width());
+        let glyph = Rect::new(target.x(), target.y(), width, height);
+        canvas
+            .copy(&tex.texture, Some(source), Some(glyph))
+            .unwrap();
+    }
 
-                let target = Rect::new(
-                    self.x + colnum as i32 * self.col_width as i32,
-                    self.y + rownum as i32 * self.row_height as i32,
-                    self.col_width as u32,
-                    self.row_height as u32,
-                );
-                if highlight_table.contains_key(&col.hl_id) {
-                    let color = highlight_table[&col.hl_id].bg;
-                    if color != -1 {
-                        canvas.set_draw_color(parse_color(color));
-                        canvas.fill_rect(target).unwrap();
+    /// Draws glyphs from shaping a run, placed from the baseline of the cell they belong to.
+    fn draw_glyphs(
+        &mut self,
+        canvas: &mut WindowCanvas,
+        font: usize,
+        glyphs: &[&ShapedGlyph],
+        color: Color,
+        target: Rect,
+    ) {
+        let baseline = target.y() + self.fonts.ascent(font);
+        for glyph in glyphs {
+            let key = GlyphCacheKey {
+                font,
+                id: glyph.id,
+                color,
+            };
+            let entry = match self.glyph_cache.get(&key) {
+                Some(entry) => entry.clone(),
+                None => {
+                    if self.glyph_cache.len() >= GLYPH_CACHE_SIZE {
+                        self.clear_glyph_cache();
                     }
+                    let entry = self.fonts.rasterize(font, glyph.id).map(|bitmap| {
+                        let surface = glyph_surface(&bitmap, color);
+                        let texture = canvas
+                            .texture_creator()
+                            .create_texture_from_surface(&surface)
+                            .unwrap();
+                        Rc::new(GlyphCacheEntry {
+                            texture,
+                            left: bitmap.left,
+                            top: bitmap.top,
+                            w: bitmap.width,
+                            h: bitmap.height,
+                        })
+                    });
+                    self.glyph_cache.insert(key, entry.clone());
+                    entry
                 }
-                canvas
-                    .copy(&texture, Some(char_rect), Some(target))
-                    .unwrap();
+            };
+            if let Some(entry) = entry {
+                let rect = Rect::new(
+                    target.x() + glyph.x + entry.left,
+                    baseline + glyph.y + entry.top,
+                    entry.w,
+                    entry.h,
+                );
+                canvas.copy(&entry.texture, None, Some(rect)).unwrap();
             }
         }
+    }
+}
 
-        canvas.set_draw_color(self.fg_color);
-        let cursor_rect = Rect::new(
-            self.x + self.cursor_col * self.col_width as i32,
-            self.y + self.cursor_row * self.row_height as i32,
-            2,
-            self.row_height as u32,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FG: Color = Color::RGB(255, 255, 255);
+    const BG: Color = Color::RGB(0, 0, 0);
+
+    fn highlight(fg: i64, bg: i64, reverse: bool) -> Highlight {
+        Highlight {
+            fg,
+            bg,
+            reverse,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn plain_colors() {
+        assert_eq!(resolve_colors(None, FG, BG), (FG, None));
+        assert_eq!(
+            resolve_colors(Some(&highlight(-1, -1, false)), FG, BG),
+            (FG, None)
+        );
+        assert_eq!(
+            resolve_colors(Some(&highlight(0xff0000, 0x00ff00, false)), FG, BG),
+            (Color::RGB(255, 0, 0), Some(Color::RGB(0, 255, 0)))
+        );
+    }
+
+    #[test]
+    fn blend() {
+        assert_eq!(blend_alpha(0), 255);
+        assert_eq!(blend_alpha(20), 204);
+        assert_eq!(blend_alpha(100), 0);
+        assert_eq!(blend_alpha(-5), 255);
+    }
+
+    #[test]
+    fn reverse_colors() {
+        assert_eq!(
+            resolve_colors(Some(&highlight(0xff0000, 0x00ff00, true)), FG, BG),
+            (Color::RGB(0, 255, 0), Some(Color::RGB(255, 0, 0)))
+        );
+        // Defaults are swapped too, so the background has to be drawn.
+        assert_eq!(
+            resolve_colors(Some(&highlight(-1, -1, true)), FG, BG),
+            (BG, Some(FG))
+        );
+        assert_eq!(
+            resolve_colors(Some(&highlight(0xff0000, -1, true)), FG, BG),
+            (BG, Some(Color::RGB(255, 0, 0)))
         );
-        canvas.fill_rect(cursor_rect).unwrap();
     }
 }
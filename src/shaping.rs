@@ -0,0 +1,452 @@
+use std::convert::TryInto;
+use std::ops::Range;
+use std::sync::Arc;
+
+use ab_glyph_rasterizer::{point, Point, Rasterizer};
+use rustybuzz::ttf_parser::{GlyphId, OutlineBuilder, Tag};
+use rustybuzz::{Direction, Face, Feature, UnicodeBuffer};
+
+/// An OpenType feature to turn on or off when shaping, like `ss01` or `calt`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FontFeature {
+    pub tag: [u8; 4],
+    pub enabled: bool,
+}
+
+/// Parses a comma separated list of features, e.g. `ss01,+cv02,-calt`. A `-` turns a feature
+/// off, and a `+` or nothing turns it on.
+pub fn parse_features(value: &str) -> Result<Vec<FontFeature>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|feature| !feature.is_empty())
+        .map(|feature| {
+            let (enabled, tag) = match feature.strip_prefix('-') {
+                Some(tag) => (false, tag),
+                None => (true, feature.trim_start_matches('+')),
+            };
+            let tag: [u8; 4] = tag
+                .as_bytes()
+                .try_into()
+                .ok()
+                .filter(|tag: &[u8; 4]| tag.iter().all(u8::is_ascii_alphanumeric))
+                .ok_or(format!("Invalid font feature: {}", feature))?;
+            Ok(FontFeature { tag, enabled })
+        })
+        .collect()
+}
+
+/// Splits a row of cells into runs to shape together: neighbours with the same key, like the
+/// highlight and font. Cells without a key, like wide characters, are runs of their own, and
+/// so is the cell under the cursor, which breaks up the ligature it is in.
+pub fn runs<K: PartialEq>(keys: &[Option<K>], cursor: Option<usize>) -> Vec<Range<usize>> {
+    let mut result: Vec<Range<usize>> = Vec::new();
+    for (i, key) in keys.iter().enumerate() {
+        if let Some(last) = result.last_mut() {
+            let joins = key.is_some()
+                && *key == keys[last.start]
+                && cursor != Some(i)
+                && cursor != Some(i - 1);
+            if joins {
+                last.end = i + 1;
+                continue;
+            }
+        }
+        result.push(i..i + 1);
+    }
+    result
+}
+
+/// A glyph from shaping a run, snapped to the cell it came from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShapedGlyph {
+    pub id: u16,
+    pub cell: usize,
+    /// Offset from the cell's left edge and the baseline, in pixels, down being positive.
+    pub x: i32,
+    pub y: i32,
+}
+
+/// The result of shaping a run of cells.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShapedRun {
+    /// The cells drawn with `glyphs` instead of on their own. Empty if shaping changed none.
+    pub changed: Vec<bool>,
+    pub glyphs: Vec<ShapedGlyph>,
+}
+
+/// Returns which cells shaping changed, given the first cell of each glyph's cluster, the
+/// glyph ids, and each cell's glyph on its own. All cells of a cluster with a changed glyph
+/// count, so a ligature is drawn whole.
+fn changed_cells(glyphs: &[(usize, u16)], nominal: &[Option<u16>]) -> Vec<bool> {
+    let mut changed = vec![false; nominal.len()];
+    let mut starts: Vec<usize> = glyphs.iter().map(|&(cell, _)| cell).collect();
+    starts.sort_unstable();
+    starts.dedup();
+    for (i, &start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).cloned().unwrap_or(nominal.len());
+        let ids: Vec<u16> = glyphs
+            .iter()
+            .filter(|&&(cell, _)| cell == start)
+            .map(|&(_, id)| id)
+            .collect();
+        let same = ids.len() == end - start
+            && ids
+                .iter()
+                .zip(&nominal[start..end])
+                .all(|(&id, &nominal)| Some(id) == nominal);
+        if !same {
+            for cell in &mut changed[start..end] {
+                *cell = true;
+            }
+        }
+    }
+    changed
+}
+
+/// Places the glyphs of changed cells, given each glyph's cell and id and its `(x_advance,
+/// x_offset, y_offset)` in font units. Glyphs are snapped to their cell, and only move along
+/// within a cluster, so the font's advances can't drift from the grid.
+fn snap(
+    glyphs: &[(usize, u16)],
+    positions: &[(i32, i32, i32)],
+    changed: &[bool],
+    scale: f32,
+) -> Vec<ShapedGlyph> {
+    let mut result = Vec::new();
+    let mut pen = 0;
+    let mut last_cell = usize::MAX;
+    for (&(cell, id), &(x_advance, x_offset, y_offset)) in glyphs.iter().zip(positions) {
+        if cell != last_cell {
+            pen = 0;
+            last_cell = cell;
+        }
+        if changed[cell] {
+            result.push(ShapedGlyph {
+                id,
+                cell,
+                x: ((pen + x_offset) as f32 * scale).round() as i32,
+                y: -(y_offset as f32 * scale).round() as i32,
+            });
+        }
+        pen += x_advance;
+    }
+    result
+}
+
+/// Turns the cells of a right-to-left run from logical to visual order, given the sorted cells
+/// each glyph's cluster starts at. A cluster over several cells, like a lam-alef ligature, is
+/// drawn from its leftmost cell, which is its last.
+fn to_visual(glyphs: &mut [ShapedGlyph], changed: &mut [bool], cluster_starts: &[usize]) {
+    let len = changed.len();
+    for glyph in glyphs {
+        let end = cluster_starts.iter().find(|&&start| start > glyph.cell);
+        glyph.cell = len - end.cloned().unwrap_or(len);
+    }
+    changed.reverse();
+}
+
+/// A rasterized glyph. `left` and `top` place its top left corner relative to the pen on the
+/// baseline.
+pub struct Bitmap {
+    pub width: u32,
+    pub height: u32,
+    pub left: i32,
+    pub top: i32,
+    /// Coverage from 0 to 1, row by row.
+    pub coverage: Vec<f32>,
+}
+
+/// Feeds a glyph outline to the rasterizer, in pixels with y pointing down.
+struct Outline<'r> {
+    raster: &'r mut Rasterizer,
+    scale: f32,
+    left: f32,
+    top: f32,
+    start: Point,
+    last: Point,
+}
+
+impl Outline<'_> {
+    fn point(&self, x: f32, y: f32) -> Point {
+        point(x * self.scale - self.left, self.top - y * self.scale)
+    }
+}
+
+impl OutlineBuilder for Outline<'_> {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.start = self.point(x, y);
+        self.last = self.start;
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let p = self.point(x, y);
+        self.raster.draw_line(self.last, p);
+        self.last = p;
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p1, p) = (self.point(x1, y1), self.point(x, y));
+        self.raster.draw_quad(self.last, p1, p);
+        self.last = p;
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p1, p2, p) = (self.point(x1, y1), self.point(x2, y2), self.point(x, y));
+        self.raster.draw_cubic(self.last, p1, p2, p);
+        self.last = p;
+    }
+
+    fn close(&mut self) {
+        if self.last != self.start {
+            self.raster.draw_line(self.last, self.start);
+        }
+        self.last = self.start;
+    }
+}
+
+/// Shapes runs of text with a font's OpenType tables, for ligatures, and rasterizes the glyphs
+/// that come out, which SDL_ttf can only draw by character.
+pub struct Shaper {
+    data: Arc<Vec<u8>>,
+    index: u32,
+}
+
+impl Shaper {
+    /// Takes the contents of a font file and the index of the face in it.
+    pub fn new(data: Arc<Vec<u8>>, index: u32) -> Result<Self, String> {
+        Face::from_slice(&data, index).ok_or("Not a font")?;
+        Ok(Self { data, index })
+    }
+
+    fn face(&self) -> Face<'_> {
+        // Checked in `new`.
+        Face::from_slice(&self.data, self.index).unwrap()
+    }
+
+    /// Shapes a run of cells, each holding one grapheme cluster, and returns the glyphs of the
+    /// cells shaping changed. Those are usually none, and the cells are drawn as they are.
+    ///
+    /// Runs of `rtl` cells are taken to be in visual order, as nvim lays them out in
+    /// 'rightleft' windows, so they are shaped from the last cell for their contextual forms.
+    /// Windows without 'rightleft' have their text in logical order instead, so there, with
+    /// 'noarabicshape', initial and final forms come out swapped. nvim doesn't tell which
+    /// windows are which.
+    pub fn shape(
+        &self,
+        cells: &[&str],
+        features: &[FontFeature],
+        size: u16,
+        rtl: bool,
+    ) -> ShapedRun {
+        let face = self.face();
+        let logical: Vec<&str> = if rtl {
+            cells.iter().rev().cloned().collect()
+        } else {
+            cells.to_vec()
+        };
+        let mut text = String::new();
+        let mut starts = Vec::new();
+        for cell in &logical {
+            starts.push(text.len());
+            text.push_str(cell);
+        }
+        let features: Vec<Feature> = features
+            .iter()
+            .map(|f| Feature::new(Tag::from_bytes(&f.tag), f.enabled as u32, ..))
+            .collect();
+        let mut buffer = UnicodeBuffer::new();
+        buffer.push_str(&text);
+        if rtl {
+            buffer.set_direction(Direction::RightToLeft);
+        }
+        let output = rustybuzz::shape(&face, &features, buffer);
+
+        let cell_of = |cluster: u32| match starts.binary_search(&(cluster as usize)) {
+            Ok(cell) => cell,
+            Err(next) => next - 1,
+        };
+        let glyphs: Vec<(usize, u16)> = output
+            .glyph_infos()
+            .iter()
+            .map(|info| (cell_of(info.cluster), info.glyph_id as u16))
+            .collect();
+        let nominal: Vec<Option<u16>> = logical
+            .iter()
+            .map(|cell| {
+                let mut chars = cell.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => face.glyph_index(c).map(|id| id.0),
+                    _ => None,
+                }
+            })
+            .collect();
+        let mut changed = changed_cells(&glyphs, &nominal);
+
+        let scale = f32::from(size) / face.units_per_em() as f32;
+        let positions: Vec<(i32, i32, i32)> = output
+            .glyph_positions()
+            .iter()
+            .map(|position| (position.x_advance, position.x_offset, position.y_offset))
+            .collect();
+        let mut result = snap(&glyphs, &positions, &changed, scale);
+        if rtl {
+            let mut cluster_starts: Vec<usize> = glyphs.iter().map(|&(cell, _)| cell).collect();
+            cluster_starts.sort_unstable();
+            to_visual(&mut result, &mut changed, &cluster_starts);
+        }
+        if !changed.contains(&true) {
+            return ShapedRun::default();
+        }
+        ShapedRun {
+            changed,
+            glyphs: result,
+        }
+    }
+
+    /// Rasterizes a glyph at `size` pixels per em. Returns `None` for glyphs without an
+    /// outline, like spaces.
+    pub fn rasterize(&self, id: u16, size: u16) -> Option<Bitmap> {
+        let face = self.face();
+        let bbox = face.glyph_bounding_box(GlyphId(id))?;
+        let scale = f32::from(size) / face.units_per_em() as f32;
+        let left = (f32::from(bbox.x_min) * scale).floor();
+        let top = (f32::from(bbox.y_max) * scale).ceil();
+        let width = (f32::from(bbox.x_max) * scale).ceil() - left;
+        let height = top - (f32::from(bbox.y_min) * scale).floor();
+        if width < 1.0 || height < 1.0 {
+            return None;
+        }
+        let mut raster = Rasterizer::new(width as usize, height as usize);
+        let mut outline = Outline {
+            raster: &mut raster,
+            scale,
+            left,
+            top,
+            start: point(0.0, 0.0),
+            last: point(0.0, 0.0),
+        };
+        face.outline_glyph(GlyphId(id), &mut outline)?;
+        let mut coverage = vec![0.0; width as usize * height as usize];
+        raster.for_each_pixel(|i, alpha| coverage[i] = alpha.min(1.0));
+        Some(Bitmap {
+            width: width as u32,
+            height: height as u32,
+            left: left as i32,
+            top: -top as i32,
+            coverage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_feature_list() {
+        assert_eq!(
+            parse_features(" ss01, +cv02,-calt"),
+            Ok(vec![
+                FontFeature {
+                    tag: *b"ss01",
+                    enabled: true,
+                },
+                FontFeature {
+                    tag: *b"cv02",
+                    enabled: true,
+                },
+                FontFeature {
+                    tag: *b"calt",
+                    enabled: false,
+                },
+            ])
+        );
+        assert_eq!(parse_features(""), Ok(vec![]));
+        assert!(parse_features("liga,toolong").is_err());
+        assert!(parse_features("c lt").is_err());
+    }
+
+    #[test]
+    fn runs_break_on_keys_and_cursor() {
+        let keys = [Some(1), Some(1), Some(1), None, Some(1), Some(2), Some(2)];
+        assert_eq!(runs(&keys, None), vec![0..3, 3..4, 4..5, 5..7]);
+        assert_eq!(
+            runs(&keys, Some(1)),
+            vec![0..1, 1..2, 2..3, 3..4, 4..5, 5..7]
+        );
+        assert_eq!(runs(&keys, Some(6)), vec![0..3, 3..4, 4..5, 5..6, 6..7]);
+    }
+
+    #[test]
+    fn unchanged_glyphs() {
+        let nominal = [Some(10), Some(11), Some(12)];
+        let glyphs = [(0, 10), (1, 11), (2, 12)];
+        assert_eq!(changed_cells(&glyphs, &nominal), vec![false; 3]);
+    }
+
+    #[test]
+    fn ligatures_cover_their_cells() {
+        // "->" joined into one glyph, followed by an untouched cell.
+        let nominal = [Some(10), Some(11), Some(12)];
+        assert_eq!(
+            changed_cells(&[(0, 50), (2, 12)], &nominal),
+            vec![true, true, false]
+        );
+        // Contextual alternates keep one glyph per cell.
+        assert_eq!(
+            changed_cells(&[(0, 10), (1, 60), (2, 12)], &nominal),
+            vec![false, true, false]
+        );
+    }
+
+    #[test]
+    fn glyphs_snap_to_their_cells() {
+        let glyph = |id, cell, x, y| ShapedGlyph { id, cell, x, y };
+        // A 1000 unit em at 10 px: a ligature over cells 0 and 1 with a mark moved up and left
+        // after it, then a cell whose advance doesn't match the grid.
+        let glyphs = [(0, 50), (0, 51), (2, 12), (3, 13)];
+        let positions = [(1200, 0, 0), (0, -300, 240), (630, 0, 0), (600, 14, -16)];
+        let changed = [true, true, true, true];
+        assert_eq!(
+            snap(&glyphs, &positions, &changed, 0.01),
+            vec![
+                glyph(50, 0, 0, 0),
+                glyph(51, 0, 9, -2),
+                glyph(12, 2, 0, 0),
+                glyph(13, 3, 0, 0),
+            ]
+        );
+        // Unchanged cells are drawn on their own.
+        let changed = [true, true, false, true];
+        let snapped = snap(&glyphs, &positions, &changed, 0.1);
+        assert_eq!(
+            snapped.iter().map(|g| g.cell).collect::<Vec<_>>(),
+            vec![0, 0, 3]
+        );
+        assert_eq!(snapped[2], glyph(13, 3, 1, 2));
+    }
+
+    #[test]
+    fn right_to_left_cells_in_visual_order() {
+        let glyph = |id, cell| ShapedGlyph {
+            id,
+            cell,
+            x: 0,
+            y: 0,
+        };
+        // Logically: lam and alef joined into one glyph, a final beh, then a space. Visually
+        // the space comes first and the ligature last, drawn from its left cell.
+        let mut glyphs = vec![glyph(90, 0), glyph(91, 2)];
+        let mut changed = vec![true, true, true, false];
+        to_visual(&mut glyphs, &mut changed, &[0, 2, 3]);
+        assert_eq!(glyphs, vec![glyph(90, 2), glyph(91, 1)]);
+        assert_eq!(changed, vec![false, true, true, true]);
+
+        // A mark with its own glyph stays in its letter's cell.
+        let mut glyphs = vec![glyph(92, 0), glyph(93, 0), glyph(94, 1)];
+        let mut changed = vec![true, true];
+        to_visual(&mut glyphs, &mut changed, &[0, 0, 1]);
+        assert_eq!(glyphs, vec![glyph(92, 1), glyph(93, 1), glyph(94, 0)]);
+    }
+}
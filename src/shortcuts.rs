@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use crate::keyboard;
+
+/// Actions handled by nvim-sdl itself instead of being sent to nvim.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    ZoomIn,
+    ZoomOut,
+    ZoomReset,
+    ToggleFullscreen,
+    Copy,
+    Paste,
+    NewWindow,
+    DebugOverlay,
+    /// Asks nvim to quit, like closing the window.
+    Quit,
+}
+
+const ACTION_NAMES: &[(&str, Action)] = &[
+    ("zoom_in", Action::ZoomIn),
+    ("zoom_out", Action::ZoomOut),
+    ("zoom_reset", Action::ZoomReset),
+    ("toggle_fullscreen", Action::ToggleFullscreen),
+    ("copy", Action::Copy),
+    ("paste", Action::Paste),
+    ("new_window", Action::NewWindow),
+    ("debug_overlay", Action::DebugOverlay),
+    ("quit", Action::Quit),
+];
+
+const DEFAULT_SHORTCUTS: &[(&str, Action)] = &[
+    ("<D-=>", Action::ZoomIn),
+    ("<D-+>", Action::ZoomIn),
+    ("<D-->", Action::ZoomOut),
+    ("<D-0>", Action::ZoomReset),
+    ("<D-C-f>", Action::ToggleFullscreen),
+    ("<D-c>", Action::Copy),
+    ("<D-v>", Action::Paste),
+    ("<D-n>", Action::NewWindow),
+    ("<D-F12>", Action::DebugOverlay),
+];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTION_NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, action)| *action)
+    }
+}
+
+/// Brings a key in nvim notation into the form `keyboard::translate` produces, so that `<c-d-F>`
+/// and `<D-C-f>` name the same shortcut. Like in nvim, case doesn't matter for letters with Ctrl,
+/// where shift has to be spelled out, and shift turns letters upper-case otherwise.
+fn normalize(key: &str) -> String {
+    if !key.starts_with('<') || !key.ends_with('>') || key.len() < 3 {
+        return key.to_string();
+    }
+    let inner = &key[1..key.len() - 1];
+    let mut rest = inner;
+    let mut gui = false;
+    let mut alt = false;
+    let mut ctrl = false;
+    let mut shift = false;
+    while rest.len() > 2 && rest.as_bytes()[1] == b'-' {
+        match rest.as_bytes()[0] {
+            b'D' | b'd' => gui = true,
+            b'M' | b'm' | b'A' | b'a' => alt = true,
+            b'C' | b'c' => ctrl = true,
+            b'S' | b's' => shift = true,
+            _ => break,
+        }
+        rest = &rest[2..];
+    }
+    let mut chars = rest.chars();
+    let rest = match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_alphabetic() && (ctrl || gui || alt) => {
+            let upper = if ctrl {
+                shift
+            } else {
+                shift || c.is_uppercase()
+            };
+            shift = shift && ctrl;
+            if upper {
+                c.to_uppercase().collect()
+            } else {
+                c.to_lowercase().collect()
+            }
+        }
+        _ => keyboard::key_name(rest).unwrap_or(rest).to_string(),
+    };
+    let mut mods = String::new();
+    for (held, prefix) in &[(gui, "D-"), (alt, "M-"), (ctrl, "C-"), (shift, "S-")] {
+        if *held {
+            mods.push_str(prefix);
+        }
+    }
+    format!("<{}{}>", mods, rest)
+}
+
+/// Maps keys, in nvim notation, to client actions.
+pub struct Shortcuts {
+    table: HashMap<String, Action>,
+}
+
+impl Shortcuts {
+    pub fn map(&mut self, key: &str, action: Action) {
+        self.table.insert(normalize(key), action);
+    }
+
+    pub fn unmap(&mut self, key: &str) {
+        self.table.remove(&normalize(key));
+    }
+
+    /// Returns the action bound to `key`, or `None` if the key should go to nvim.
+    pub fn lookup(&self, key: &str) -> Option<Action> {
+        self.table.get(key).cloned()
+    }
+}
+
+impl Default for Shortcuts {
+    fn default() -> Self {
+        let mut shortcuts = Shortcuts {
+            table: HashMap::new(),
+        };
+        for (key, action) in DEFAULT_SHORTCUTS {
+            shortcuts.map(key, *action);
+        }
+        shortcuts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_reorders_modifiers() {
+        assert_eq!(normalize("<D-=>"), "<D-=>");
+        assert_eq!(normalize("<c-d-f>"), "<D-C-f>");
+        assert_eq!(normalize("<A-S-C-D-F12>"), "<D-M-C-S-F12>");
+        assert_eq!(normalize("<D-->"), "<D-->");
+        assert_eq!(normalize("<F11>"), "<F11>");
+        assert_eq!(normalize("x"), "x");
+    }
+
+    #[test]
+    fn normalize_folds_keys() {
+        assert_eq!(normalize("<D-C-F>"), "<D-C-f>");
+        assert_eq!(normalize("<c-s-f>"), "<C-S-F>");
+        assert_eq!(normalize("<D-S-s>"), "<D-S>");
+        assert_eq!(normalize("<D-F>"), "<D-F>");
+        assert_eq!(normalize("<c-space>"), "<C-Space>");
+        assert_eq!(normalize("<s-tab>"), "<S-Tab>");
+        assert_eq!(normalize("<d-f12>"), "<D-F12>");
+        assert_eq!(normalize("<M-lt>"), "<M-lt>");
+    }
+
+    #[test]
+    fn defaults_and_overrides() {
+        let mut shortcuts = Shortcuts::default();
+        assert_eq!(shortcuts.lookup("<D-=>"), Some(Action::ZoomIn));
+        assert_eq!(shortcuts.lookup("<D-C-f>"), Some(Action::ToggleFullscreen));
+        assert_eq!(shortcuts.lookup("<C-f>"), None);
+        shortcuts.map("<D-C-F>", Action::Quit);
+        assert_eq!(shortcuts.lookup("<D-C-f>"), Some(Action::Quit));
+
+        shortcuts.unmap("<d-c>");
+        assert_eq!(shortcuts.lookup("<D-c>"), None);
+        shortcuts.map("<F11>", Action::ToggleFullscreen);
+        assert_eq!(shortcuts.lookup("<F11>"), Some(Action::ToggleFullscreen));
+    }
+
+    #[test]
+    fn action_names() {
+        for (name, action) in ACTION_NAMES {
+            assert_eq!(Action::from_name(name), Some(*action));
+        }
+        assert_eq!(Action::from_name("zoom"), None);
+    }
+}
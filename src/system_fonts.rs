@@ -0,0 +1,135 @@
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use font_kit::handle::Handle;
+use font_kit::source::SystemSource;
+use rustybuzz::ttf_parser::Face;
+
+use crate::font::FontFace;
+
+/// An installed face and the characters it has, as sorted, inclusive ranges of code points.
+struct IndexedFace {
+    face: FontFace,
+    ranges: Vec<(u32, u32)>,
+}
+
+/// Which installed faces have which characters, for characters none of the configured fonts
+/// have. The fonts are read once, on another thread, and the index is shared by every `Fonts`
+/// it is cloned into.
+#[derive(Clone, Default)]
+pub struct SystemFonts {
+    index: Arc<Mutex<Option<Vec<IndexedFace>>>>,
+    started: Arc<AtomicBool>,
+}
+
+impl SystemFonts {
+    /// Starts reading the installed fonts, unless that already started.
+    pub fn start(&self) {
+        if self.started.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let index = self.index.clone();
+        thread::spawn(move || {
+            let faces = read_system_fonts();
+            *index.lock().unwrap() = Some(faces);
+        });
+    }
+
+    /// Returns the installed faces that have `c`, in the order the system lists them, or `None`
+    /// while the fonts are still being read.
+    pub fn find(&self, c: char) -> Option<Vec<FontFace>> {
+        self.start();
+        let index = self.index.lock().unwrap();
+        let faces = index
+            .as_ref()?
+            .iter()
+            .filter(|indexed| contains(&indexed.ranges, c as u32))
+            .map(|indexed| indexed.face.clone())
+            .collect();
+        Some(faces)
+    }
+}
+
+fn read_system_fonts() -> Vec<IndexedFace> {
+    let handles = SystemSource::new().all_fonts().unwrap_or_default();
+    let mut faces = Vec::new();
+    for handle in handles {
+        let (path, index) = match handle {
+            Handle::Path { path, font_index } => (path, font_index),
+            Handle::Memory { .. } => continue,
+        };
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(_) => continue,
+        };
+        if let Ok(face) = Face::parse(&data, index) {
+            faces.push(IndexedFace {
+                ranges: ranges(code_points(&face)),
+                face: FontFace { path, index },
+            });
+        }
+    }
+    faces
+}
+
+/// The code points the face has glyphs for, in no particular order.
+fn code_points(face: &Face<'_>) -> Vec<u32> {
+    let mut code_points = Vec::new();
+    let subtables = face.tables().cmap.iter().flat_map(|cmap| cmap.subtables);
+    for subtable in subtables.filter(|subtable| subtable.is_unicode()) {
+        subtable.codepoints(|c| {
+            if subtable.glyph_index(c).is_some_and(|id| id.0 != 0) {
+                code_points.push(c);
+            }
+        });
+    }
+    code_points
+}
+
+/// Merges code points into sorted, inclusive ranges.
+fn ranges(mut code_points: Vec<u32>) -> Vec<(u32, u32)> {
+    code_points.sort_unstable();
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for c in code_points {
+        match ranges.last_mut() {
+            Some((_, end)) if c <= *end + 1 => *end = c.max(*end),
+            _ => ranges.push((c, c)),
+        }
+    }
+    ranges
+}
+
+fn contains(ranges: &[(u32, u32)], c: u32) -> bool {
+    ranges
+        .binary_search_by(|&(start, end)| {
+            if end < c {
+                std::cmp::Ordering::Less
+            } else if start > c {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_code_points() {
+        let ranges = ranges(vec![0x42, 0x41, 0x43, 0x41, 0x100, 0x3000, 0x2fff]);
+        assert_eq!(ranges, vec![(0x41, 0x43), (0x100, 0x100), (0x2fff, 0x3000)]);
+        assert!(contains(&ranges, 0x41));
+        assert!(contains(&ranges, 0x42));
+        assert!(contains(&ranges, 0x100));
+        assert!(contains(&ranges, 0x3000));
+        assert!(!contains(&ranges, 0x40));
+        assert!(!contains(&ranges, 0x44));
+        assert!(!contains(&ranges, 0x3001));
+        assert!(!contains(&[], 0x41));
+    }
+}